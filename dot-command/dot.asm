@@ -36,11 +36,17 @@ start:
     printMsg msg_my_ip
     printMsg Wifi.ipAddr
     printMsg new_line
+    call Wifi.saveDir
     call Wifi.recv
     IFDEF NEXT
     call restoreCpuSpeed
     ENDIF
-    ret
+    ; the file received asked to be run
+    ld a, (Wifi.run)
+    or a
+    ret z
+    ld hl, start_dir
+    jp EsxDOS.exec
 msg_my_ip db "Device IP: ", 0
 new_line db 13, "Listening port: 6144", 13, 0
 
@@ -75,7 +81,9 @@ savedCpuSpeed: db 0
     savebin "zxmit", text, $ - text
 
 recv_buffer = $
-data_buffer = ($ + 1024 + 32)
-data_end = data_buffer + 4096
+data_buffer = ($ + Wifi.RECV_BUFFER_SIZE)
+data_end = data_buffer + Wifi.DATA_BUFFER_SIZE
+; at the very end, so a longer path can only run into the ROM
+start_dir = 16384 - Wifi.START_DIR_SIZE
 
-    assert data_end < 16384
+    assert data_end <= start_dir
@@ -105,6 +105,7 @@ readBlock:
     dec de
     ld a, d: or e
     jr nz, readBlock
+    ret
 
 uartRead:
     ld hl, _isSecondByteAvail
@@ -5,38 +5,112 @@ ESX_EXEC = #8F
 ESX_FOPEN = #9A
 ESX_FCLOSE = #9B
 ESX_FSYNC = #9C
+ESX_FREAD = #9D
 ESX_FWRITE = #9E
+ESX_FSEEK = #9F
+ESX_FGETPOS = #A0
+ESX_GETCWD = #A8
+ESX_CHDIR = #A9
+ESX_MKDIR = #AA
 
 
 FMODE_CREATE = #0E
+FMODE_CREATE_NEW = #06
+FMODE_WRITE = #02
+FMODE_READ_WRITE = #03
+
+; File exists, equ as wifi.asm refers to it before it's defined
+ESX_EEXIST equ 18
+
+SEEK_START = 0
 
 CMD_BUFF = 23512
+; longest command line to exec along with the terminator, equ as wifi.asm
+; refers to it before it's defined
+EXEC_LINE_SIZE equ 40
 
+;; HL - file name, the file is replaced if it exists
+;; Carry set on error
 open:
+    ld b, FMODE_CREATE
+    jr openMode
+
+;; HL - file name of a file that doesn't exist
+;; Carry set on error, A - error code, ESX_EEXIST if the file exists
+create:
+    ld b, FMODE_CREATE_NEW
+
+;; HL - file name
+;; B - mode
+;; Carry set on error
+openMode:
+    push hl, bc
+    xor a
+    rst #8
+    db ESX_GETSETDRV
+
+    pop bc, hl
+    rst #8 
+    db ESX_FOPEN
+    ret c
+
+    ld (fhandle), a
+    ret
+
+;; HL - file name of an existing file
+;; Carry set on error
+reopen:
     push hl
     xor a
     rst #8
     db ESX_GETSETDRV
 
     pop hl
-    ld b, FMODE_CREATE
-    rst #8 
+    ld b, FMODE_READ_WRITE
+    rst #8
     db ESX_FOPEN
-    jp c, .err
+    ret c
 
     ld (fhandle), a
     ret
-.err
-    pop ix
+
+;; BCDE - offset from the start of the file
+;; Carry set on error
+seek:
+    ld a, (fhandle)
+    ld l, SEEK_START
+    rst #8 : db ESX_FSEEK
+    ret
+
+;; BCDE - current offset in the file
+;; Carry set on error
+getPos:
+    ld a, (fhandle)
+    rst #8 : db ESX_FGETPOS
+    ret
+
+;; HL - buffer
+;; BC - chunk size
+;; Carry set on error, BC - bytes read, less at the end of the file
+read:
+    ld a, (fhandle)
+    rst #8 : db ESX_FREAD
     ret
 
 ;; HL - buffer
 ;; BC - chunk size
+;; Carry set on error
 write:
     ld a, (fhandle)
     rst #8 : db ESX_FWRITE
     ret
 
+;; Carry set on error
+sync:
+    ld a, (fhandle)
+    rst #8 : db ESX_FSYNC
+    ret
+
 close:
     ld a, (fhandle)
     rst #8 : db ESX_FSYNC
@@ -47,5 +121,80 @@ close:
     ei
     ret
 
+;; HL - directory name
+;; Carry set on error
+mkdir:
+    push hl
+    xor a
+    rst #8
+    db ESX_GETSETDRV
+
+    pop hl
+    rst #8 : db ESX_MKDIR
+    ret
+
+;; HL - directory name
+;; Carry set on error
+chdir:
+    push hl
+    xor a
+    rst #8
+    db ESX_GETSETDRV
+
+    pop hl
+    rst #8 : db ESX_CHDIR
+    ret
+
+;; A - drive, letter in bits 7..3 from 0 for A, bits 2..0 not all zero
+;; Carry set on error
+setDrive:
+    rst #8 : db ESX_GETSETDRV
+    ret
+
+;; A - current drive
+getDrive:
+    xor a
+    rst #8 : db ESX_GETSETDRV
+    ret
+
+;; HL - buffer for the path of the current directory
+;; Carry set on error
+getCwd:
+    push hl
+    xor a
+    rst #8
+    db ESX_GETSETDRV
+
+    pop hl
+    rst #8 : db ESX_GETCWD
+    ret
+
+;; HL - zero terminated dot command line without the dot, up to
+;; EXEC_LINE_SIZE bytes with the terminator
+;; Runs it as a nested dot command, which is loaded over this one, so the
+;; line is copied onto the stack first
+;; Carry set on error, A - error code
+exec:
+    ; room for the line, EXEC_LINE_SIZE is even
+    ld b, EXEC_LINE_SIZE / 2
+1:
+    push bc
+    djnz 1b
+    ex de, hl
+    ld hl, 0
+    add hl, sp
+    ex de, hl
+    push de
+    ld bc, EXEC_LINE_SIZE
+    ldir
+    pop hl
+    rst #8 : db ESX_EXEC
+    ; pops leave the result in A and carry alone
+    ld b, EXEC_LINE_SIZE / 2
+1:
+    pop de
+    djnz 1b
+    ret
+
 fhandle db 0
     endmodule
@@ -85,52 +85,246 @@ LONG_HEADER_SIZE  equ 17
 SHORT_HEADER_SIZE equ 4
 FLAGS_COMPRESSED  equ 1
 FLAGS_LONG_HEADER equ 2
+FLAGS_DIRECTORY   equ 4
+FLAGS_RESUME      equ 8
+FLAGS_CRC         equ 16
+FLAGS_LENGTH      equ 32
+FLAGS_PROBE       equ 64
+FLAGS_LONG_NAME   equ 128
+LENGTH_SIZE       equ 2
+CRC_SIZE          equ 2
+CRC_INIT          equ #FFFF
+RESUME_SIZE       equ 4
+
+; Long header without a name is a control packet, the type follows the
+; terminator
+PACKET_END        equ 1
+PACKET_RUN        equ 2
+
+ERROR_SIZE_MISMATCH equ 1
+ERROR_NOT_OPENED    equ 2
+ERROR_OPEN          equ 3
+ERROR_WRITE         equ 4
+ERROR_DIRECTORY     equ 5
+ERROR_RESUME        equ 6
+ERROR_CRC           equ 7
+ERROR_EXISTS        equ 8
+ERROR_RUN           equ 9
+ERROR_FILE_SIZE     equ 10
+
+; Codes from here up report the decision on the file opened, not an error
+STATUS_CREATED      equ #80
+STATUS_OVERWRITTEN  equ #81
+; File was written out in full and closed
+STATUS_COMMITTED    equ #82
+STATUS_RENAMED      equ #90
+
+; What to do if the file exists, POLICY_NONE overwrites it without a status
+POLICY_NONE         equ 0
+POLICY_OVERWRITE    equ 1
+POLICY_FAIL         equ 2
+POLICY_RENAME       equ 3
+
+RECV_BUFFER_SIZE    equ 1024 + 32
+DATA_BUFFER_SIZE    equ 4096
+; Longest path of the current directory, with long names
+START_DIR_SIZE      equ 256
+
+; Reads the rest of the frame the ESP delivers and processes the packets
+; in it as they complete, the last one may continue in the next frame
+; A - error code
+processFrame:
+    ld hl, (frame_left)
+    ld a, h : or l
+    ret z
+
+    call packetSize
+    ld bc, RECV_BUFFER_SIZE + 1
+    or a
+    sbc hl, bc
+    jr nc, .tooLong
+    add hl, bc
+
+    ; read as much of the packet as the frame holds
+    ld bc, (packet_len)
+    or a
+    sbc hl, bc
+    ld de, (frame_left)
+    push hl
+    or a
+    sbc hl, de
+    pop hl
+    jr c, 1f
+    ex hl, de
+1:
+    ex hl, de
+    ld hl, (frame_left)
+    or a
+    sbc hl, de
+    ld (frame_left), hl
+    ld hl, (packet_len)
+    push hl
+    add hl, de
+    ld (packet_len), hl
+    pop bc
+    ld hl, recv_buffer
+    add hl, bc
+    call Uart.readBlock
+
+    ld hl, (packet_len)
+    ld bc, SHORT_HEADER_SIZE
+    or a
+    sbc hl, bc
+    jr c, processFrame
+    call packetSize
+    ld bc, (packet_len)
+    or a
+    sbc hl, bc
+    jr nz, processFrame
+
+    ; packet is complete, the next one starts from the beginning
+    ld (packet_len), hl
+    call processPacket
+    or a
+    jr nz, .drain
+    ld a, (packets_buffered) : or a
+    jr nz, processFrame
+    ld a, '+' : rst #10
+    jr processFrame
+
+.tooLong
+    ; error, packet doesn't fit into the buffer
+    ld a, '!' : rst #10
+    ld a, ERROR_SIZE_MISMATCH
+.drain
+    ; the rest of the frame has to be read before sending the ACK
+    push af
+    ld hl, (frame_left)
+1:
+    ld a, h : or l
+    jr z, 2f
+    push hl
+    call Uart.read
+    pop hl
+    dec hl
+    jr 1b
+2:
+    ld (frame_left), hl
+    pop af
+    ret
+
+; HL - length of the packet in recv_buffer according to its header, just
+; the short header if it's not received yet
+; Sets header_size
+packetSize:
+    ld hl, (packet_len)
+    ld bc, SHORT_HEADER_SIZE
+    or a
+    sbc hl, bc
+    ld hl, SHORT_HEADER_SIZE
+    ret c
 
-processPacket
-    ; does EsxDOS need it preserved?
-    push ix
-    ld ix, recv_buffer
-    ld a, (ix + 0) ; sequence
-    ld (seq), a
-    ld h, (ix + 2)
-    ld l, (ix + 1)
-    pop ix
     ld bc, LONG_HEADER_SIZE
     ld a, (recv_buffer + 3)
     and FLAGS_LONG_HEADER
     jr nz, 1f
     ld bc, SHORT_HEADER_SIZE
+1:
+    ld a, (recv_buffer + 3)
+    and FLAGS_LENGTH
+    jr z, 1f
+    ; decompressed length follows the header
+    inc bc : inc bc
 1:
     ld (header_size), bc
+    ld hl, (recv_buffer + 1)
     add hl, bc
-    ld bc, (data_size)
-    sbc hl, bc
-    ld a, h
-    or l
-    jr z, 1f
-
-    ; error, packet size doesn't match the header
-    ld a, '!' : rst #10
-    ld a, 1
+    ld a, (recv_buffer + 3)
+    and FLAGS_CRC
+    ret z
+    ; CRC follows the payload
+    inc hl : inc hl
     ret
 
+; Processes the complete packet in recv_buffer
+; A - error code
+processPacket:
+    ld a, (recv_buffer + 3)
+    and FLAGS_PROBE
+    jr z, 1f
+    ; probe needs nothing but the ACK
+    xor a
+    ret
 1:
+    ld a, 1
+    ld (connection_used), a
+    ld a, (recv_buffer + 3)
+    and FLAGS_LONG_HEADER
+    jr z, .notControl
+    ld a, (recv_buffer + 4)
+    or a
+    jr nz, .notControl
+    ld a, (recv_buffer + 5)
+    cp PACKET_END : jp z, .endFile
+    cp PACKET_RUN : jp z, .run
+    ; error, unknown control packet
+    ld a, '!' : rst #10
+    ld a, ERROR_SIZE_MISMATCH
+    ret
+.notControl
+    ld a, (recv_buffer + 3)
+    and FLAGS_DIRECTORY
+    jp nz, .directory
+    ld a, (recv_buffer + 3)
+    and FLAGS_RESUME
+    jp nz, .resume
+    ld a, (recv_buffer + 3)
+    and FLAGS_LONG_HEADER | FLAGS_LONG_NAME
+    jr nz, .newFile
     ld a, (file_opened)
     or a
     jr nz, 2f
-    ld a, (recv_buffer + 3)
-    and FLAGS_LONG_HEADER
-    jr nz, 1f
 
     ; file is not opened, but we recevied a short header.
     ld a, '@' : rst #10
-    ld a, 2
+    ld a, ERROR_NOT_OPENED
+    ret
+
+.newFile
+    ; long header starts the next file, so close the current one
+    ld a, (file_opened)
+    or a
+    jr z, 1f
+    call closeFile
+    jp c, .writeErr
+    ld a, 13 : rst #10
+1:
+    call getName
+    call openFile
+    jr nc, 1f
+
+    ; failed to open the file, or it exists and the policy doesn't allow
+    ; to replace it
+    push af
+    cp ERROR_EXISTS
+    ld a, '#'
+    jr nz, 3f
+    ld a, '='
+3:
+    rst #10
+    pop af
     ret
 1:
-    ld hl, recv_buffer + 4
-    call EsxDOS.open
     ld a, 1
     ld (file_opened), a
+    ld hl, CRC_INIT
+    ld (file_crc), hl
+    ld a, (recv_buffer + 3)
+    and FLAGS_LONG_NAME
+    jr z, 2f
+    ; long name takes the whole payload, the data follows in the next packets
+    xor a
+    ret
 
 2:
     ld a, (recv_buffer + 3)
@@ -146,15 +340,38 @@ processPacket
     add hl, bc
     ex hl, de
     call dzx0_standard
+    ; DE is past the unpacked data
+    push de
 
     ld bc, 1024
-    jr .write
+    ld a, (recv_buffer + 3)
+    and FLAGS_LENGTH
+    jr z, 1f
+    ; partial block, take its length from the header
+    ld hl, recv_buffer - LENGTH_SIZE
+    ld bc, (header_size)
+    add hl, bc
+    ld c, (hl)
+    inc hl
+    ld b, (hl)
+1:
+    ; block unpacks into exactly that many bytes, or it's broken
+    pop de
+    ld hl, (data_buffered)
+    add hl, bc
+    push bc
+    ld bc, data_buffer
+    add hl, bc
+    pop bc
+    or a
+    sbc hl, de
+    jr z, .write
+    ld a, '!' : rst #10
+    ld a, ERROR_SIZE_MISMATCH
+    ret
 
 .notcompressed:
-    ld hl, (data_size)
-    ld bc, (header_size)
-    sbc hl, bc
-    ld bc, hl
+    ld bc, (recv_buffer + 1)
     push bc
     ld bc, (header_size)
     ld hl, recv_buffer
@@ -169,6 +386,14 @@ processPacket
     pop bc
 
 .write
+    ld a, (recv_buffer + 3)
+    and FLAGS_CRC
+    jr z, 1f
+    push bc
+    call checkCrc
+    pop bc
+    jp c, .crcErr
+1:
     ld hl, (data_buffered)
     add hl, bc
     ld (data_buffered), hl
@@ -182,6 +407,7 @@ processPacket
     ld hl, data_buffer
     ld bc, (data_buffered)
     call EsxDOS.write
+    jr c, .writeErr
     ld bc, 0
     ld (data_buffered), bc
 .skipWrite
@@ -189,6 +415,519 @@ processPacket
     ld a, 0
     ret
 
+.writeErr
+    ; failed to write the file
+    ld a, '$' : rst #10
+    ld a, ERROR_WRITE
+    ret
+
+.directory
+    ; close the current file, create the directory and enter it
+    ld a, (file_opened)
+    or a
+    jr z, 1f
+    call closeFile
+    jr c, .writeErr
+    ld a, 13 : rst #10
+1:
+    call getName
+    push hl
+    inc hl
+    ld a, (hl) : cp ':' : jr nz, 1f
+    inc hl
+    ld a, (hl) : or a : jr nz, 1f
+
+    ; drive letter and a colon select the drive, letter goes to bits 7..3
+    pop hl
+    ld a, (hl)
+    and #1F
+    dec a
+    add a : add a : add a
+    or 1
+    call EsxDOS.setDrive
+    jr nc, .entered
+    jr .dirErr
+1:
+    ; it's fine if the directory already exists
+    pop hl : push hl
+    call EsxDOS.mkdir
+    pop hl
+    call EsxDOS.chdir
+    jr nc, .entered
+
+.dirErr
+    ; failed to enter the directory
+    ld a, '%' : rst #10
+    ld a, ERROR_DIRECTORY
+    ret
+.entered
+    ld a, '/' : rst #10
+    xor a
+    ret
+
+.resume
+    ; close the current file, reopen the interrupted one and continue
+    ; at the offset from the payload
+    ld a, (file_opened)
+    or a
+    jr z, 1f
+    call closeFile
+    jp c, .writeErr
+    ld a, 13 : rst #10
+1:
+    ; CRC of the data before the offset follows the payload, it's taken
+    ; before a long name gets terminated in its place
+    ld a, (recv_buffer + 3)
+    and FLAGS_CRC
+    jr z, 1f
+    ld hl, recv_buffer
+    ld de, (header_size)
+    add hl, de
+    ld de, (recv_buffer + 1)
+    add hl, de
+    ld e, (hl) : inc hl : ld d, (hl)
+    ld (file_crc), de
+1:
+    call getName
+    call EsxDOS.reopen
+    jr c, .resumeErr
+    ld a, 1
+    ld (file_opened), a
+    ; offset starts the payload
+    ld hl, recv_buffer
+    ld de, (header_size)
+    add hl, de
+    ld e, (hl) : inc hl : ld d, (hl) : inc hl
+    ld c, (hl) : inc hl : ld b, (hl)
+    ld a, (recv_buffer + 3)
+    and FLAGS_CRC
+    jr nz, .checkPrefix
+    call EsxDOS.seek
+    jr c, .resumeErr
+.resumed
+    ld a, '>' : rst #10
+    xor a
+    ret
+.checkPrefix
+    ; data already on disk has to match the CRC of the client too, so the
+    ; CRC covers the whole file, reading it leaves the file at the offset
+    call prefixCrc
+    jr c, .resumeErr
+    ld de, (file_crc)
+    or a
+    sbc hl, de
+    jr nz, .crcErr
+    jr .resumed
+
+.resumeErr
+    ; failed to reopen the file or seek in it
+    ld a, '&' : rst #10
+    ld a, ERROR_RESUME
+    ret
+
+.crcErr
+    ; data got corrupted
+    ld a, '*' : rst #10
+    ld a, ERROR_CRC
+    ret
+
+.run
+    ; close the current file and keep the command line from the payload to
+    ; run it once the connection is closed, it takes the place of the start
+    ; directory that isn't restored then
+    ld a, (file_opened)
+    or a
+    jr z, 1f
+    call closeFile
+    jp c, .writeErr
+    ld a, 13 : rst #10
+1:
+    ld bc, (recv_buffer + 1)
+    ld a, b : or c
+    jr z, .runErr
+    ld hl, EsxDOS.EXEC_LINE_SIZE - 1
+    or a
+    sbc hl, bc
+    jr c, .runErr
+    ld hl, recv_buffer + LONG_HEADER_SIZE
+    ld de, start_dir
+    ldir
+    xor a
+    ld (de), a
+    inc a
+    ld (run), a
+    ld a, '^' : rst #10
+    xor a
+    ret
+
+.runErr
+    ; command line doesn't fit
+    ld a, '?' : rst #10
+    ld a, ERROR_RUN
+    ret
+
+.endFile
+    ; write out the rest of the file, check that all of it is there and
+    ; close it, the ACK confirms it's on the card
+    ld a, (file_opened)
+    or a
+    jr nz, 1f
+    ld a, '@' : rst #10
+    ld a, ERROR_NOT_OPENED
+    ret
+1:
+    call flushFile
+    jp c, .writeErr
+    call EsxDOS.sync
+    jp c, .writeErr
+    call EsxDOS.getPos
+    jp c, .writeErr
+    ; file size starts the payload
+    ld hl, (recv_buffer + LONG_HEADER_SIZE)
+    or a
+    sbc hl, de
+    jr nz, .sizeErr
+    ld hl, (recv_buffer + LONG_HEADER_SIZE + 2)
+    sbc hl, bc
+    jr nz, .sizeErr
+    call closeFile
+    ld a, 13 : rst #10
+    ld a, STATUS_COMMITTED
+    ld (file_status), a
+    xor a
+    ret
+
+.sizeErr
+    ; some of the file is missing
+    ld a, '-' : rst #10
+    ld a, ERROR_FILE_SIZE
+    ret
+
+; HL - zero terminated name of the file or directory in the packet
+; Long name comes in a short header packet and ends its payload, it's
+; terminated in place of whatever follows it
+; Collision policy goes to policy, it ends the long header or precedes the
+; long name of a file that's opened
+getName:
+    xor a
+    ld (policy), a
+    ld a, (recv_buffer + 3)
+    and FLAGS_LONG_NAME
+    jr nz, .long
+    ; policy takes the place of the terminator of a 12 character name
+    ld hl, recv_buffer + LONG_HEADER_SIZE - 1
+    ld a, (hl)
+    ld (policy), a
+    ld (hl), 0
+    ld hl, recv_buffer + 4
+    ret
+.long
+    ld hl, recv_buffer + SHORT_HEADER_SIZE
+    ld de, (recv_buffer + 1)
+    add hl, de
+    ld (hl), 0
+    ld hl, recv_buffer + SHORT_HEADER_SIZE
+    ld a, (recv_buffer + 3)
+    and FLAGS_RESUME
+    jr z, 1f
+    ; offset of the resume packet goes first
+    ld hl, recv_buffer + SHORT_HEADER_SIZE + RESUME_SIZE
+    ret
+1:
+    ld a, (recv_buffer + 3)
+    and FLAGS_DIRECTORY
+    ret nz
+    ld a, (hl)
+    ld (policy), a
+    inc hl
+    ret
+
+; Creates file HL as policy tells, file_status gets the decision on it
+; Carry set on error, A - error code
+openFile:
+    ld a, (policy)
+    or a
+    jr nz, 1f
+    ; client didn't choose a policy, the file is replaced silently
+    call EsxDOS.open
+    ld a, ERROR_OPEN
+    ret
+1:
+    ld (name_ptr), hl
+    call EsxDOS.create
+    jr c, 2f
+    ld a, STATUS_CREATED
+    jr .opened
+2:
+    cp EsxDOS.ESX_EEXIST : jr nz, .openErr
+    ld a, (policy)
+    cp POLICY_FAIL : jr z, .exists
+    cp POLICY_RENAME : jr z, .rename
+    ; overwrite, unknown policies too
+    ld hl, (name_ptr)
+    call EsxDOS.open
+    jr c, .openErr
+    ld a, STATUS_OVERWRITTEN
+.opened
+    ld (file_status), a
+    or a
+    ret
+.rename
+    ; try NAME~1 to NAME~9
+    ld hl, (name_ptr)
+    call tildeName
+.next
+    ld hl, (name_ptr)
+    call EsxDOS.create
+    jr nc, .renamed
+    cp EsxDOS.ESX_EEXIST : jr nz, .openErr
+    ld hl, (tilde_digit)
+    ld a, (hl)
+    cp '9' : jr z, .exists
+    inc (hl)
+    jr .next
+.renamed
+    ld hl, (tilde_digit)
+    ld a, (hl)
+    sub '0'
+    add STATUS_RENAMED
+    jr .opened
+.exists
+    ld a, ERROR_EXISTS
+    scf
+    ret
+.openErr
+    ld a, ERROR_OPEN
+    scf
+    ret
+
+; Inserts "~1" into zero terminated name HL in front of the extension, DOS
+; 8.3 names keep up to 6 characters before it
+; Sets tilde_digit to the digit
+tildeName:
+    push hl
+    ; BC - the last dot, or the terminator if there's none
+    ld bc, 0
+1:
+    ld a, (hl)
+    or a
+    jr z, 2f
+    cp '.'
+    jr nz, 3f
+    ld b, h : ld c, l
+3:
+    inc hl
+    jr 1b
+2:
+    ld (tilde_end), hl
+    ld a, b : or c
+    jr nz, 1f
+    ld b, h : ld c, l
+1:
+    ; HL - insertion point, the dot or the 7th character of a short name
+    pop de
+    ld h, b : ld l, c
+    ld a, (recv_buffer + 3)
+    and FLAGS_LONG_NAME
+    jr nz, 1f
+    ld hl, 6
+    add hl, de
+    push hl
+    or a
+    sbc hl, bc
+    pop hl
+    jr c, 1f
+    ld h, b : ld l, c
+1:
+    ld (tilde_digit), hl
+    ; move the extension along with the terminator 2 characters past the
+    ; insertion point, it never moves left
+    ld hl, (tilde_end)
+    or a
+    sbc hl, bc
+    inc hl
+    push hl
+    ld de, (tilde_digit)
+    inc de : inc de
+    dec hl
+    add hl, de
+    ex de, hl
+    ld hl, (tilde_end)
+    pop bc
+    lddr
+
+    ld hl, (tilde_digit)
+    ld (hl), '~'
+    inc hl
+    ld (hl), '1'
+    ld (tilde_digit), hl
+    ret
+
+; Continues file_crc over BC bytes of the block just put into data_buffer
+; and compares it with the CRC that follows the payload
+; Carry set on mismatch
+checkCrc:
+    ld hl, data_buffer
+    ld de, (data_buffered)
+    add hl, de
+    ex de, hl
+    ld hl, (file_crc)
+    call crc16
+    ld (file_crc), hl
+    ex de, hl
+    ld hl, recv_buffer
+    ld bc, (header_size)
+    add hl, bc
+    ld bc, (recv_buffer + 1)
+    add hl, bc
+    ld a, (hl)
+    inc hl
+    ld h, (hl)
+    ld l, a
+    or a
+    sbc hl, de
+    ret z
+    scf
+    ret
+
+; Continues the CRC in HL over BC bytes from DE, BC isn't zero
+; HL - CRC, DE - past the data
+crc16:
+.loop
+    ; CRC-16/CCITT-FALSE without a table, H - high byte, L - low byte
+    ld a, (de)
+    xor h
+    ld h, l
+    ld l, a
+    rrca : rrca : rrca : rrca
+    and #0F
+    xor l
+    ld l, a
+    rrca : rrca : rrca : rrca
+    and #F0
+    xor h
+    ld h, a
+    ld a, l
+    rrca : rrca : rrca
+    and #1F
+    xor h
+    ld h, a
+    ld a, l
+    rrca : rrca : rrca
+    and #E0
+    xor l
+    ld l, a
+
+    inc de
+    dec bc
+    ld a, b : or c
+    jr nz, .loop
+    ret
+
+; CRC of the data before the offset in BCDE, read from the start of the
+; reopened file through data_buffer, which leaves the file at the offset
+; HL - CRC, carry set if the file is shorter or can't be read
+prefixCrc:
+    ld (resume_left), de
+    ld (resume_left + 2), bc
+    ld bc, 0 : ld de, 0
+    call EsxDOS.seek
+    ret c
+    ld hl, CRC_INIT
+    ld (prefix_crc), hl
+.chunk
+    ; a whole buffer while the offset is 64K or more away
+    ld bc, DATA_BUFFER_SIZE
+    ld hl, (resume_left + 2)
+    ld a, h : or l
+    jr nz, .read
+    ld hl, (resume_left)
+    ld a, h : or l
+    ld hl, (prefix_crc)
+    ret z
+    ld hl, (resume_left)
+    ld de, DATA_BUFFER_SIZE
+    sbc hl, de
+    jr nc, .read
+    ld bc, (resume_left)
+.read
+    push bc
+    ld hl, data_buffer
+    call EsxDOS.read
+    pop de
+    ret c
+    ; fewer bytes than asked for, the file ends before the offset
+    ld a, b : cp d : jr nz, .short
+    ld a, c : cp e : jr nz, .short
+    ld hl, (resume_left)
+    or a
+    sbc hl, bc
+    ld (resume_left), hl
+    jr nc, 1f
+    ld hl, (resume_left + 2)
+    dec hl
+    ld (resume_left + 2), hl
+1:
+    ld de, data_buffer
+    ld hl, (prefix_crc)
+    call crc16
+    ld (prefix_crc), hl
+    jr .chunk
+.short
+    scf
+    ret
+
+; Remembers the drive and directory the command was started in
+saveDir:
+    call EsxDOS.getDrive
+    ld (start_drive), a
+    ld hl, start_dir
+    call EsxDOS.getCwd
+    ret nc
+    ; there's no way back without it
+    xor a
+    ld (start_dir), a
+    ret
+
+; Goes back to the drive and directory the command was started in, unless
+; there's a command to run, which starts where the file is
+restoreDir:
+    ld a, (run)
+    or a
+    ret nz
+    ld a, (start_drive)
+    call EsxDOS.setDrive
+    ld a, (start_dir)
+    or a
+    ret z
+    ld hl, start_dir
+    jp EsxDOS.chdir
+
+; Writes out buffered data and closes the file
+; Carry set on write error
+closeFile:
+    xor a
+    ld (file_opened), a
+    call flushFile
+    push af
+    call EsxDOS.close
+    pop af
+    ret
+
+; Writes out buffered data, the file stays open
+; Carry set on write error
+flushFile:
+    xor a
+    ld (packets_buffered), a
+    ld hl, data_buffer
+    ld bc, (data_buffered)
+    ld a, b : or c
+    ret z
+    call EsxDOS.write
+    ld bc, 0
+    ld (data_buffered), bc
+    ret
+
 recv:
     call Uart.read
     cp 'L' : jp z, .closedBegins 
@@ -222,18 +961,23 @@ recv:
 
 .storeDataSize
     ld (data_size), hl
-    ex hl, de
-    ld hl, recv_buffer
-    call Uart.readBlock
-
-    call processPacket
+    ld (frame_left), hl
+    call processFrame
     or a
-    jr nz, .packetErr
-    ld a, (packets_buffered) : or a
-    jr nz, .skipProgress
-    ld a, '+' : rst #10
+    jr nz, 1f
+    ; report the decision on the file opened in the frame, or the commit of
+    ; the one it ended, if any
+    ld hl, file_status
+    ld a, (hl)
+    ld (hl), 0
+1:
+    ld (error_code), a
+    ; ACK carries the sequence of the packet the frame ended in, or the
+    ; failed one
+    ld a, (recv_buffer)
+    ld (seq), a
 
-.skipProgress:
+    ; ACK is sent even on error, so the PC knows what went wrong
     EspSend "AT+CIPSEND="
     ld hl, socket_num
     call espSendZ
@@ -249,7 +993,7 @@ recv:
     jr nz, .wait
     ld a, (seq)
     call Uart.write
-    ld a, 0
+    ld a, (error_code)
     call Uart.write
     ld a, (data_size)
     call Uart.write
@@ -257,6 +1001,11 @@ recv:
     call Uart.write
     ld a, 13: call Uart.write
     ld a, 10: call Uart.write
+    ; status codes aren't errors
+    ld a, (error_code)
+    dec a
+    cp STATUS_CREATED - 1
+    jr c, .packetErr
     jp recv
 .packetErr:
     ld hl, .errPacket : call Display.putStr
@@ -271,16 +1020,33 @@ recv:
     call Uart.read : cp 'E' : jp nz, recv
     call Uart.read : cp 'D' : jp nz, recv
 
+    ; connection that only probed isn't the transfer, keep listening
+    ld a, (connection_used)
+    or a
+    jr z, .listen
+    ; neither is one that dropped before the file was committed, the PC
+    ; reconnects from the start directory to resume it
+    ld a, (file_opened)
+    or a
+    jr z, .exit
+    call closeFile
+    jr c, .exit
+    ld a, 13: rst #10
+    call restoreDir
+    xor a
+    ld (connection_used), a
+.listen
+    ld hl, 0
+    ld (packet_len), hl
+    jp recv
+
 .exit:
     EspCmd "AT+CIPSERVER=0,1"
+    call restoreDir
     ld a, (file_opened)
     or a
     ret z
-    ld hl, data_buffer
-    ld bc, (data_buffered)
-    ld a, b: or c
-    call nz, EsxDOS.write
-    call EsxDOS.close
+    call closeFile
     ld a, 13: rst #10
     ret
 
@@ -381,11 +1147,27 @@ checkOkErr:
     ret
 
 seq db 0
+error_code db 0
 counter db 0
 file_opened db 0
 data_size dw 0
+frame_left dw 0
+packet_len dw 0
 header_size dw 0
 data_buffered dw 0
 packets_buffered db 0
+start_drive db 0
+connection_used db 0
+; start_dir holds the command line to run once the connection is closed
+run db 0
+file_crc dw CRC_INIT
+; left to read by prefixCrc and its CRC so far
+resume_left dd 0
+prefix_crc dw 0
+policy db POLICY_NONE
+file_status db 0
+name_ptr dw 0
+tilde_end dw 0
+tilde_digit dw 0
 socket_num db "00000000", 0
     endmodule
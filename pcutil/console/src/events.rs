@@ -0,0 +1,411 @@
+//! Newline delimited JSON events printed with `--json`, one object per line
+
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+use zxmit_protocol::{Ack, Error, Progress, RunError, Stall, UploadEvent};
+
+/// Where the transfer of a file got to, mirrors `Progress`
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub current_block: usize,
+    pub blocks_num: usize,
+    pub total_bytes: usize,
+    pub sent_bytes: usize,
+    pub compressed_bytes: usize,
+    pub crc: Option<u16>,
+    pub quick_saved: usize,
+    pub optimal_saved: usize,
+    /// "created", "overwritten" or "renamed", only with --collision
+    pub decision: Option<String>,
+    /// Receiver confirmed the file is written out in full
+    pub committed: bool,
+}
+
+impl From<&Progress> for Status {
+    fn from(progress: &Progress) -> Status {
+        Status {
+            current_block: progress.current_block,
+            blocks_num: progress.blocks_num,
+            total_bytes: progress.total_bytes,
+            sent_bytes: progress.sent_bytes,
+            compressed_bytes: progress.compressed_bytes,
+            crc: progress.crc,
+            quick_saved: progress.quick_saved,
+            optimal_saved: progress.optimal_saved,
+            decision: progress.decision.map(|decision| decision.to_string()),
+            committed: progress.committed,
+        }
+    }
+}
+
+/// Why a file wasn't sent
+#[derive(Debug, Serialize)]
+#[serde(tag = "cause", rename_all = "snake_case")]
+pub enum Cause {
+    /// File couldn't be read or queued
+    File { message: String },
+    /// Name doesn't fit into the header
+    Name { message: String, max_len: usize },
+    Resolve { message: String, host: String },
+    Connect { message: String, address: String, tried: Vec<String> },
+    /// Receiver reported an error in the ACK
+    Receiver { message: String, code: u8, block: Option<usize>, seq: u8, offset: Option<usize> },
+    /// Receiver stopped responding
+    Timeout { message: String, stall: &'static str, after: f64, block: usize, seq: u8, offset: usize },
+    Io { message: String, kind: String },
+    /// File can't be run as asked
+    Run { message: String },
+    /// Transfer stopped before getting to the file
+    NotSent { message: String },
+}
+
+impl Cause {
+    pub fn file(message: impl Into<String>) -> Cause {
+        Cause::File { message: message.into() }
+    }
+
+    pub fn not_sent() -> Cause {
+        Cause::NotSent { message: "not sent".to_string() }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Cause::File { message }
+            | Cause::Name { message, .. }
+            | Cause::Resolve { message, .. }
+            | Cause::Connect { message, .. }
+            | Cause::Receiver { message, .. }
+            | Cause::Timeout { message, .. }
+            | Cause::Io { message, .. }
+            | Cause::Run { message }
+            | Cause::NotSent { message } => message,
+        }
+    }
+}
+
+impl From<&Error> for Cause {
+    fn from(e: &Error) -> Cause {
+        let message = e.to_string();
+        match e {
+            Error::Name(_, max_len) => Cause::Name { message, max_len: *max_len },
+            Error::Resolve(address, _) => Cause::Resolve { message, host: address.host.clone() },
+            Error::Connect(address, attempts) => Cause::Connect {
+                message,
+                address: address.to_string(),
+                tried: attempts.iter().map(|(addr, _)| addr.to_string()).collect(),
+            },
+            Error::Receiver(e) => Cause::Receiver {
+                message,
+                code: e.error.code(),
+                block: e.block,
+                seq: e.seq,
+                offset: e.offset,
+            },
+            Error::Timeout(e) => Cause::Timeout {
+                message,
+                stall: match e.stall {
+                    Stall::Write => "write",
+                    Stall::Ack => "ack",
+                },
+                after: e.after.as_secs_f64(),
+                block: e.block,
+                seq: e.seq,
+                offset: e.offset,
+            },
+            Error::Io(e) => Cause::Io { message, kind: format!("{:?}", e.kind()) },
+        }
+    }
+}
+
+impl From<&RunError> for Cause {
+    fn from(e: &RunError) -> Cause {
+        Cause::Run { message: e.to_string() }
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Connected { address: String },
+    /// Connection dropped, `cause` is why
+    Reconnecting {
+        #[serde(flatten)]
+        cause: Cause,
+        retries_left: usize,
+        delay: f64,
+    },
+    FileStarted { path: &'a str, name: &'a str, size: usize },
+    /// Block went out, `raw` bytes of the file took `wire` bytes on the wire
+    BlockSent { index: usize, seq: u8, raw: usize, wire: usize, compression: String },
+    Ack { seq: u8, size: u16, status: u8 },
+    Progress {
+        #[serde(flatten)]
+        status: Status,
+    },
+    /// Receiver was asked to run the file, `only_attaches` is set if the
+    /// command doesn't start it
+    Run { command: &'a str, only_attaches: bool },
+    FileDone {
+        path: &'a str,
+        name: &'a str,
+        saved_as: String,
+        #[serde(flatten)]
+        status: Status,
+    },
+    Error {
+        path: &'a str,
+        name: &'a str,
+        #[serde(flatten)]
+        cause: &'a Cause,
+    },
+    Summary {
+        sent: usize,
+        failed: usize,
+        total_bytes: usize,
+        compressed_bytes: usize,
+        quick_saved: usize,
+        optimal_saved: usize,
+        elapsed: f64,
+    },
+}
+
+impl From<&UploadEvent> for Event<'_> {
+    fn from(event: &UploadEvent) -> Self {
+        match *event {
+            UploadEvent::Sent { index, seq, raw_len, wire_len, compressed_with } => Event::BlockSent {
+                index,
+                seq,
+                raw: raw_len,
+                wire: wire_len,
+                compression: compressed_with.to_string(),
+            },
+            UploadEvent::Ack(Ack { seq, error, size }) => Event::Ack { seq, size, status: error },
+        }
+    }
+}
+
+impl Event<'_> {
+    pub fn reconnecting(e: &Error, retries_left: usize, delay: Duration) -> Event<'static> {
+        Event::Reconnecting { cause: e.into(), retries_left, delay: delay.as_secs_f64() }
+    }
+}
+
+/// Prints `event` as a single line
+pub fn emit(event: &Event) {
+    let mut stdout = io::stdout().lock();
+    // Nobody left to tell if stdout is gone
+    if serde_json::to_writer(&mut stdout, event).is_ok() {
+        let _ = writeln!(stdout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use zxmit_protocol::{Address, AckError, Compression, Decision, ReceiverError, TimeoutError};
+
+    fn to_json(event: &Event) -> Value {
+        serde_json::to_value(event).unwrap()
+    }
+
+    fn progress() -> Progress {
+        Progress {
+            current_block: 3,
+            blocks_num: 3,
+            total_bytes: 2100,
+            sent_bytes: 2100,
+            compressed_bytes: 900,
+            crc: Some(0x29b1),
+            quick_saved: 1200,
+            optimal_saved: 40,
+            decision: Some(Decision::Renamed(1)),
+            committed: true,
+        }
+    }
+
+    fn status_json() -> Value {
+        json!({
+            "current_block": 3,
+            "blocks_num": 3,
+            "total_bytes": 2100,
+            "sent_bytes": 2100,
+            "compressed_bytes": 900,
+            "crc": 0x29b1,
+            "quick_saved": 1200,
+            "optimal_saved": 40,
+            "decision": "renamed",
+            "committed": true,
+        })
+    }
+
+    /// `status_json` with `fields` on top, as flattened into an event.
+    fn with_status(fields: Value) -> Value {
+        let mut value = status_json();
+        value.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        value
+    }
+
+    #[test]
+    fn connected() {
+        let event = Event::Connected { address: "192.168.1.5:6144".to_string() };
+        assert_eq!(to_json(&event), json!({"event": "connected", "address": "192.168.1.5:6144"}));
+    }
+
+    #[test]
+    fn reconnecting() {
+        let e = Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        assert_eq!(to_json(&Event::reconnecting(&e, 2, Duration::from_millis(1500))), json!({
+            "event": "reconnecting",
+            "cause": "io",
+            "message": "reset",
+            "kind": "ConnectionReset",
+            "retries_left": 2,
+            "delay": 1.5,
+        }));
+    }
+
+    #[test]
+    fn file_started() {
+        let event = Event::FileStarted { path: "games/GAME.SNA", name: "GAME.SNA", size: 49179 };
+        assert_eq!(to_json(&event), json!({
+            "event": "file_started",
+            "path": "games/GAME.SNA",
+            "name": "GAME.SNA",
+            "size": 49179,
+        }));
+    }
+
+    #[test]
+    fn block_sent() {
+        let sent =
+            UploadEvent::Sent { index: 4, seq: 5, raw_len: 1024, wire_len: 612, compressed_with: Compression::Quick };
+        assert_eq!(to_json(&Event::from(&sent)), json!({
+            "event": "block_sent",
+            "index": 4,
+            "seq": 5,
+            "raw": 1024,
+            "wire": 612,
+            "compression": "quick",
+        }));
+    }
+
+    #[test]
+    fn ack() {
+        let ack = UploadEvent::Ack(Ack { seq: 5, error: 0x82, size: 616 });
+        assert_eq!(to_json(&Event::from(&ack)), json!({"event": "ack", "seq": 5, "size": 616, "status": 0x82}));
+    }
+
+    #[test]
+    fn progress_event() {
+        let event = Event::Progress { status: (&progress()).into() };
+        assert_eq!(to_json(&event), with_status(json!({"event": "progress"})));
+
+        let event = Event::Progress { status: (&Progress::default()).into() };
+        let value = to_json(&event);
+        assert_eq!((&value["crc"], &value["decision"]), (&Value::Null, &Value::Null));
+        assert_eq!(value["committed"], json!(false));
+    }
+
+    #[test]
+    fn run() {
+        let event = Event::Run { command: "snapload GAME.SNA", only_attaches: false };
+        assert_eq!(to_json(&event), json!({"event": "run", "command": "snapload GAME.SNA", "only_attaches": false}));
+    }
+
+    #[test]
+    fn file_done() {
+        let event = Event::FileDone {
+            path: "games/GAME.SNA",
+            name: "GAME.SNA",
+            saved_as: "GAME~1.SNA".to_string(),
+            status: (&progress()).into(),
+        };
+        assert_eq!(to_json(&event), with_status(json!({
+            "event": "file_done",
+            "path": "games/GAME.SNA",
+            "name": "GAME.SNA",
+            "saved_as": "GAME~1.SNA",
+        })));
+    }
+
+    #[test]
+    fn summary() {
+        let event = Event::Summary {
+            sent: 2,
+            failed: 1,
+            total_bytes: 4096,
+            compressed_bytes: 2048,
+            quick_saved: 1800,
+            optimal_saved: 248,
+            elapsed: 2.5,
+        };
+        assert_eq!(to_json(&event), json!({
+            "event": "summary",
+            "sent": 2,
+            "failed": 1,
+            "total_bytes": 4096,
+            "compressed_bytes": 2048,
+            "quick_saved": 1800,
+            "optimal_saved": 248,
+            "elapsed": 2.5,
+        }));
+    }
+
+    /// Error event for `cause`, checked without the message.
+    fn error_json(cause: &Cause) -> Value {
+        let mut value = to_json(&Event::Error { path: "a/B.BIN", name: "B.BIN", cause });
+        let fields = value.as_object_mut().unwrap();
+        for (field, expected) in [("event", json!("error")), ("path", json!("a/B.BIN")), ("name", json!("B.BIN")),
+            ("message", json!(cause.to_string()))] {
+            assert_eq!(fields.remove(field), Some(expected), "{}", field);
+        }
+        value
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error_json(&Cause::file("no such file")), json!({"cause": "file"}));
+        assert_eq!(error_json(&Cause::not_sent()), json!({"cause": "not_sent"}));
+        assert_eq!(error_json(&(&RunError::Extension("B.BIN".to_string())).into()), json!({"cause": "run"}));
+        assert_eq!(error_json(&(&Error::Name("LONG_NAME.BIN".to_string(), 12)).into()),
+            json!({"cause": "name", "max_len": 12}));
+
+        let address = Address { host: "speccy".to_string(), port: 6144 };
+        let resolve = Error::Resolve(address.clone(), io::Error::other("unknown host"));
+        assert_eq!(error_json(&(&resolve).into()), json!({"cause": "resolve", "host": "speccy"}));
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let connect = Error::Connect(address, vec![("10.0.0.2:6144".parse().unwrap(), refused)]);
+        assert_eq!(error_json(&(&connect).into()),
+            json!({"cause": "connect", "address": "speccy:6144", "tried": ["10.0.0.2:6144"]}));
+
+        let receiver =
+            Error::Receiver(ReceiverError { error: AckError::Crc, block: Some(2), seq: 3, offset: Some(2048) });
+        assert_eq!(error_json(&(&receiver).into()),
+            json!({"cause": "receiver", "code": 7, "block": 2, "seq": 3, "offset": 2048}));
+        let unmatched = Error::Receiver(ReceiverError { error: AckError::Write, block: None, seq: 9, offset: None });
+        assert_eq!(error_json(&(&unmatched).into()),
+            json!({"cause": "receiver", "code": 4, "block": null, "seq": 9, "offset": null}));
+
+        let timeout = Error::Timeout(TimeoutError {
+            stall: Stall::Ack,
+            after: Duration::from_millis(2500),
+            block: 1,
+            seq: 2,
+            offset: 1024,
+        });
+        assert_eq!(error_json(&(&timeout).into()),
+            json!({"cause": "timeout", "stall": "ack", "after": 2.5, "block": 1, "seq": 2, "offset": 1024}));
+
+        let io = Error::Io(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(error_json(&(&io).into()), json!({"cause": "io", "kind": "BrokenPipe"}));
+    }
+}
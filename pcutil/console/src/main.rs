@@ -1,227 +1,1036 @@
-use clap::Parser;
-use log::{error, info};
-use regex::Regex;
+mod events;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use clap::builder::TypedValueParser;
+use clap::error::ErrorKind;
+use log::{Level, LevelFilter, error, info, log_enabled, warn};
 use simple_logger::SimpleLogger;
-use std::net::Ipv4Addr;
-use std::path::Path;
-use std::time;
-use zx0::{CompressionResult, Compressor};
-use indicatif::ProgressBar;
-use tokio::io::AsyncWriteExt;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{self, Duration};
 use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc;
-use tokio::net::TcpStream;
+use events::{Cause, Event, emit};
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use zxmit_protocol::{Address, CHUNK_SIZE, Collision, Compression, Connection, DEFAULT_ACK_TIMEOUT,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_DEBOUNCE, DEFAULT_PORT, DEFAULT_PROBE_TIMEOUT, DEFAULT_WINDOW,
+    DEFAULT_WRITE_TIMEOUT, Destination, Driver, Error, MAX_WINDOW, Pacing, Pipeline, Progress, RawReason, Report,
+    Timeouts, UploadEvent, Uploader, Watcher, dirname_to_short, filename_to_long, filename_to_short, only_attaches,
+    remote_name, run_command};
 
 const CARGO_PKG_VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+/// Pause before reconnecting, gives the Spectrum time to notice the dropped connection
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// File name that stands for the standard input
+const STDIN: &str = "-";
 
 /// Utility to send arbitrary files to a WiFi equipped ZX Spectrum
 #[derive(Debug, Parser)]
-#[command(about)]
+#[command(about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Arguments {
-    /// IP address of ZX Spectrum that's runs zxmit
-    pub ip: Ipv4Addr,
-    /// File name of filename to deliver
-    pub filename: String,
-    /// Dummy run without any networking communication
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Host name or IP address of ZX Spectrum that's runs zxmit, optionally followed by :port.
+    /// IPv6 addresses go in brackets: [fe80::1]:6144
+    #[arg(required = true)]
+    pub address: Option<String>,
+    /// Files to deliver, wildcards are expanded. "-" reads the standard input, which needs --as
+    #[arg(required = true)]
+    pub filenames: Vec<String>,
+    /// Send directories with all their contents, recreating the tree on the Spectrum
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// Dummy run without any networking communication, prints the remote layout
     #[arg(short, long)]
     pub dummy: bool,
-    /// Don't use compression
+    /// Don't use compression, same as --compression none
     #[arg(short, long)]
     pub no_compression: bool,
+    /// Compression effort: quick, optimal, auto (whichever is smaller for every block) or none
+    #[arg(short, long, value_name = "MODE", default_value_t = Compression::Quick)]
+    pub compression: Compression,
+    /// Make the Spectrum verify CRC of the received data, older versions of the dot command reject it
+    #[arg(long)]
+    pub crc: bool,
+    /// Port to connect to, unless it's given in the address
+    #[arg(short, long, default_value_t = DEFAULT_PORT, value_parser = clap::value_parser!(u16).range(1..))]
+    pub port: u16,
+    /// Seconds to wait for the connection, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_CONNECT_TIMEOUT.as_secs())]
+    pub connect_timeout: u64,
+    /// Seconds to wait for a block to be sent, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_WRITE_TIMEOUT.as_secs())]
+    pub write_timeout: u64,
+    /// Seconds to wait for the Spectrum to acknowledge a block, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_ACK_TIMEOUT.as_secs())]
+    pub ack_timeout: u64,
+    /// Reconnect and resume the transfer up to N times if the connection drops
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retries: usize,
+    /// Send up to N blocks ahead without waiting for their ACKs, 1 waits for every block
+    #[arg(short, long, value_name = "N", default_value_t = DEFAULT_WINDOW,
+        value_parser = clap::value_parser!(u8).range(1..=MAX_WINDOW as i64).map(usize::from))]
+    pub window: usize,
+    /// Send at most N bytes per second, headers included, for networks or receivers that can't keep up.
+    /// 0 doesn't limit it
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub rate: u32,
+    /// Milliseconds to pause after every block, on top of --rate
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub delay: u64,
+    /// Keep the file names as they are instead of converting them to DOS 8.3, for NextZXOS and
+    /// esxDOS builds that support long file names
+    #[arg(short, long)]
+    pub long_names: bool,
+    /// Name to save the file under on the Spectrum, only for a single file
+    #[arg(long = "as", value_name = "NAME")]
+    pub rename: Option<String>,
+    /// Directory to put the files into, created if it doesn't exist: [D:][/]DIR/DIR, e.g. C:/GAMES/NEW.
+    /// Relative to the current directory of the Spectrum without the leading slash
+    #[arg(long, value_name = "PATH")]
+    pub dest: Option<String>,
+    /// What the Spectrum does if a file exists: overwrite, fail (stops the transfer) or rename to NAME~1..9.
+    /// Older versions of the dot command overwrite it silently
+    #[arg(long, value_name = "POLICY")]
+    pub collision: Option<Collision>,
+    /// Print newline delimited JSON events instead of the log and the progress bar
+    #[arg(long)]
+    pub json: bool,
+    /// Make the Spectrum run the file once it's received, only for a single file. Snapshots, TAP and NEX files
+    /// are run with the dot commands that load them, files without an extension are run as dot commands.
+    /// --run="COMMAND" gives the dot command line to run instead, e.g. --run="nexload GAME.NEX"
+    #[arg(long, value_name = "COMMAND", num_args = 0..=1, require_equals = true)]
+    pub run: Option<Option<String>>,
+    /// Keep running and send the files again whenever they change, with a line per upload.
+    /// The dot command quits after every round, so it has to be run again before the next one
+    #[arg(long, conflicts_with = "recursive")]
+    pub watch: bool,
 }
 
-const LONG_HEADER_LEN: usize = 17;
-const CHUNK_SIZE: usize = 1024;
-const FLAGS_COMPRESSED: u8 = 1;
-const FLAGS_LONG_HEADER: u8 = 2;
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Find ZX Spectrums running zxmit on the local network and send files to the one found, quits the dot
+    /// commands older than v0.5
+    ///
+    /// Every host around is probed, and dot commands older than v0.5 quit after a probe, even in the middle of a
+    /// transfer. Don't scan while someone else may be sending files with an older one. Asks before scanning
+    /// unless --yes is given.
+    ///
+    /// The files are sent with the default options, only if there's a single Spectrum around.
+    Discover {
+        /// Port to probe
+        #[arg(short, long, default_value_t = DEFAULT_PORT, value_parser = clap::value_parser!(u16).range(1..))]
+        port: u16,
+        /// Seconds every host is given to answer
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_PROBE_TIMEOUT.as_secs(),
+            value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: u64,
+        /// Scan without asking, for scripts
+        #[arg(short, long)]
+        yes: bool,
+        /// Files to send to the Spectrum found
+        filenames: Vec<String>,
+    },
+    /// Show how a file would be split into blocks and compressed, with the transfer time estimates
+    Analyze {
+        /// File to analyze
+        file: String,
+        /// Compression effort: quick, optimal, auto or none
+        #[arg(short, long, value_name = "MODE", default_value_t = Compression::Quick)]
+        compression: Compression,
+        /// Send CRC of the data
+        #[arg(long)]
+        crc: bool,
+        /// Keep the file name as it is, it goes in a block of its own
+        #[arg(short, long)]
+        long_names: bool,
+    },
+}
 
-async fn transmit(
-    ip: Ipv4Addr,
-    name: Vec<u8>,
-    buffer: Vec<u8>,
-    dummy: bool,
-    no_compression: bool,
-) -> std::io::Result<()> {
-    let addr = format!("{}:6144", ip);
-
-    info!("Establishing connection to {}", &addr);
-    let mut stream = if dummy {
-        None
+/// File queued for sending
+struct Entry {
+    /// Path as given on the command line
+    path: String,
+    /// Name it's saved under on the Spectrum
+    name: String,
+    data: Result<Vec<u8>, String>,
+}
+
+/// Step of the transfer
+enum Item {
+    File(Entry),
+    /// Create the directory unless it exists and enter it
+    EnterDir { path: String, name: String },
+    /// Go back to the parent directory
+    LeaveDir { path: String },
+}
+
+/// Outcome of sending a single file
+struct Summary {
+    path: String,
+    name: String,
+    result: Result<Progress, Cause>,
+}
+
+impl Summary {
+    /// Prints the outcome as a JSON event
+    fn emit(&self, long_names: bool) {
+        match &self.result {
+            Ok(progress) => emit(&Event::FileDone {
+                path: &self.path,
+                name: &self.name,
+                saved_as: progress.decision.map_or(self.name.clone(), |decision| {
+                    decision.saved_as(&self.name, long_names)
+                }),
+                status: progress.into(),
+            }),
+            Err(cause) => emit(&Event::Error { path: &self.path, name: &self.name, cause }),
+        }
+    }
+}
+
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Expands wildcards for shells that don't do it, e.g. on Windows
+fn expand(patterns: &[String]) -> Vec<Result<String, String>> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        if !has_wildcards(pattern) || Path::new(pattern).exists() {
+            paths.push(Ok(pattern.clone()));
+            continue;
+        }
+        let matches: Vec<String> = match glob::glob(pattern) {
+            Ok(matches) => matches
+                .filter_map(|m| m.ok())
+                .map(|m| m.to_string_lossy().to_string())
+                .collect(),
+            Err(e) => {
+                paths.push(Err(format!("{}: {}", pattern, e)));
+                continue;
+            }
+        };
+        if matches.is_empty() {
+            paths.push(Err(format!("{}: no files match", pattern)));
+        }
+        paths.extend(matches.into_iter().map(Ok));
+    }
+    paths
+}
+
+fn basename(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Name `path` gets on the Spectrum
+fn converted_name(path: &str, long_names: bool) -> String {
+    let name = basename(Path::new(path));
+    if long_names {
+        filename_to_long(&name)
+    } else if Path::new(path).is_dir() {
+        dirname_to_short(&name)
     } else {
-        Some(TcpStream::connect(addr).await?)
+        filename_to_short(&name)
+    }
+}
+
+async fn read_entry(path: String, long_names: bool) -> Entry {
+    if path == STDIN {
+        // It has no name, --as gives it one
+        let mut data = Vec::new();
+        let data = tokio::io::stdin().read_to_end(&mut data).await
+            .map(|_| data)
+            .map_err(|e| e.to_string());
+        return Entry { path: "<stdin>".to_string(), name: String::new(), data };
+    }
+    let name = converted_name(&path, long_names);
+    let data = tokio::fs::read(&path).await.map_err(|e| e.to_string());
+    Entry { path, name, data }
+}
+
+/// Queues directory `path` and everything in it, sorted by name
+async fn walk(path: String, long_names: bool, items: &mut Vec<Item>, failed: &mut Vec<Summary>) {
+    let mut children = Vec::new();
+    let mut dir = match tokio::fs::read_dir(&path).await {
+        Ok(dir) => dir,
+        Err(e) => {
+            failed.push(Summary { path, name: String::new(), result: Err(Cause::file(e.to_string())) });
+            return;
+        }
     };
-    let mut compressed_bytes = 0;
-    let blocks_num = buffer.chunks(CHUNK_SIZE).len();
-    let total_bytes = buffer.len();
+    loop {
+        match dir.next_entry().await {
+            Ok(Some(child)) => children.push(child.path()),
+            Ok(None) => break,
+            Err(e) => {
+                failed.push(Summary { path, name: String::new(), result: Err(Cause::file(e.to_string())) });
+                return;
+            }
+        }
+    }
+    children.sort();
 
-    let now = time::Instant::now();
+    let name = converted_name(&path, long_names);
+    items.push(Item::EnterDir { path: path.clone(), name });
 
-    let (tx, mut rx) = mpsc::channel(16);
-    tokio::spawn(async move {
-        let mut seq: u8 = 0;
-        let mut long_header = true;
-        for chunk in buffer.chunks(CHUNK_SIZE) {
-            let mut block: Vec<u8>;
-            let compressed = if no_compression {
-                CompressionResult {
-                    output: Vec::new(),
-                    delta: 0,
-                }
-            } else {
-                Compressor::new().quick_mode(true).compress(chunk)
-            };
+    // Different names may end up the same after conversion, FAT ignores
+    // the case of long names too
+    let mut taken: HashMap<String, String> = HashMap::new();
+    for child in children {
+        let child = child.to_string_lossy().to_string();
+        let name = converted_name(&child, long_names);
+        if let Some(other) = taken.get(&name.to_ascii_uppercase()) {
+            let e = format!("{} is already taken by {}", name, other);
+            failed.push(Summary { path: child, name, result: Err(Cause::file(e)) });
+            continue;
+        }
+        taken.insert(name.to_ascii_uppercase(), child.clone());
 
-            let use_compressed: bool = !no_compression
-                && chunk.len() == CHUNK_SIZE
-                && compressed.output.len() < chunk.len();
+        if Path::new(&child).is_dir() {
+            Box::pin(walk(child, long_names, items, failed)).await;
+        } else {
+            items.push(Item::File(read_entry(child, long_names).await));
+        }
+    }
 
-            let mut to_send = if !use_compressed {
-                Vec::from(chunk)
-            } else {
-                compressed.output
-            };
+    items.push(Item::LeaveDir { path });
+}
 
-            let mut flags: u8 = 0;
+async fn read_items(patterns: &[String], recursive: bool, long_names: bool) -> (Vec<Item>, Vec<Summary>) {
+    let mut items = Vec::new();
+    let mut failed = Vec::new();
+    for path in expand(patterns) {
+        let path = match path {
+            Ok(path) => path,
+            Err(e) => {
+                failed.push(Summary { path: String::new(), name: String::new(), result: Err(Cause::file(e)) });
+                continue;
+            }
+        };
+        if path != STDIN && Path::new(&path).is_dir() {
+            if recursive {
+                walk(path, long_names, &mut items, &mut failed).await;
+            } else {
+                let e = "is a directory, use --recursive to send it".to_string();
+                failed.push(Summary { path, name: String::new(), result: Err(Cause::file(e)) });
+            }
+            continue;
+        }
+        items.push(Item::File(read_entry(path, long_names).await));
+    }
+    (items, failed)
+}
 
-            if use_compressed {
-                flags |= FLAGS_COMPRESSED;
+/// Prints the tree the files end up in on the Spectrum
+fn print_layout(items: &[Item], destination: &Destination) {
+    if destination.is_empty() {
+        println!("Remote layout:");
+    } else {
+        println!("Remote layout in {}:", destination);
+    }
+    let mut depth = 0;
+    for item in items {
+        match item {
+            Item::File(entry) => {
+                println!("{:indent$}{:12}  <- {}", "", entry.name, entry.path, indent = depth * 2);
             }
+            Item::EnterDir { path, name } => {
+                println!("{:indent$}{:12}  <- {}", "", format!("{}/", name), path, indent = depth * 2);
+                depth += 1;
+            }
+            Item::LeaveDir { .. } => depth -= 1,
+        }
+    }
+}
+
+/// Errors that may go away after reconnecting
+fn is_transient(e: &Error) -> bool {
+    matches!(e, Error::Io(_) | Error::Timeout(_) | Error::Resolve(..) | Error::Connect(..))
+}
 
-            if long_header {
-                flags |= FLAGS_LONG_HEADER;
+/// Waits before retrying after `e`, returns `e` back if there are no retries
+/// left or retrying won't help
+async fn wait_retry(retries: &mut usize, e: Error, json: bool) -> Result<(), Error> {
+    if *retries == 0 || !is_transient(&e) {
+        return Err(e);
+    }
+    *retries -= 1;
+    if json {
+        emit(&Event::reconnecting(&e, *retries, RETRY_DELAY));
+    } else {
+        warn!("{}, reconnecting in {:?}, {} retries left", e, RETRY_DELAY, retries);
+    }
+    tokio::time::sleep(RETRY_DELAY).await;
+    Ok(())
+}
+
+/// Connection that is reestablished when it drops, as long as there are
+/// retries left
+struct Transfer {
+    uploader: Uploader,
+    connection: Connection,
+    /// Directories entered so far, to enter them again after reconnecting
+    dirs: Vec<String>,
+    retries: usize,
+    /// Print the events as JSON
+    json: bool,
+}
+
+impl Transfer {
+    async fn connect(uploader: Uploader, mut retries: usize, json: bool) -> Result<Transfer, Error> {
+        info!("Establishing connection to {}", &uploader.address);
+        let connection = loop {
+            match uploader.connect().await {
+                Ok(connection) => break connection,
+                Err(e) => wait_retry(&mut retries, e, json).await?,
             }
+        };
 
-            block = vec![
-                seq,
-                (to_send.len() % 256) as u8,
-                (to_send.len() / 256) as u8,
-                flags,
-            ];
+        let transfer = Transfer {
+            uploader,
+            connection,
+            dirs: Vec::new(),
+            retries,
+            json,
+        };
+        transfer.connected();
+        Ok(transfer)
+    }
 
-            seq = seq.wrapping_add(1);
+    fn connected(&self) {
+        if self.json {
+            emit(&Event::Connected { address: self.uploader.address.to_string() });
+        }
+    }
+
+    /// Records the outcome of a file, printing it right away with --json
+    fn record(&self, summaries: &mut Vec<Summary>, summary: Summary) {
+        if self.json {
+            summary.emit(self.uploader.long_names);
+        }
+        summaries.push(summary);
+    }
 
-            if long_header {
-                block.append(&mut name.clone());
-                block.resize(LONG_HEADER_LEN, 0);
-                long_header = false;
+    /// Makes the receiver run file `name` once the connection is closed,
+    /// with `command` or the one that runs files like it
+    async fn run(&mut self, name: &str, command: Option<&str>) -> Result<(), Cause> {
+        let command = run_command(name, command).map_err(|e| Cause::from(&e))?;
+        while let Err(e) = self.connection.run(&command).await {
+            self.recover(e).await.map_err(|e| Cause::from(&e))?;
+        }
+        let only_attaches = only_attaches(&command);
+        if self.json {
+            emit(&Event::Run { command: &command, only_attaches });
+        } else if only_attaches {
+            info!("{} is attached with .{}, type LOAD \"\" to start it", name, command);
+        } else {
+            info!("{} runs with .{}", name, command);
+        }
+        Ok(())
+    }
+
+    /// Marks the files that are left as not sent
+    fn skip_rest(&self, items: impl Iterator<Item = Item>, summaries: &mut Vec<Summary>) {
+        for item in items {
+            if let Item::File(entry) = item {
+                self.record(summaries, Summary { path: entry.path, name: entry.name, result: Err(Cause::not_sent()) });
             }
-            block.append(&mut to_send);
-            tx.send(block).await.unwrap();
         }
-    });
+    }
 
-    let bar = ProgressBar::new(blocks_num as u64);
-    while let Some(block) = rx.recv().await {
-        let seq = block[0];
-        if let Some(ref mut s) = stream {
-            s.write_all(&block).await?
-        };
+    async fn reconnect(&self) -> Result<Connection, Error> {
+        let mut connection = self.uploader.connect().await?;
+        for dir in &self.dirs {
+            connection.enter_dir(dir).await?;
+        }
+        Ok(connection)
+    }
 
-        bar.inc(1);
-        compressed_bytes += block.len();
-        let mut acked = 0;
+    /// Reconnects after `e`, gives up with the last error once the retries
+    /// are used up
+    async fn recover(&mut self, mut e: Error) -> Result<(), Error> {
         loop {
-            // ACK is:
-            // 0: sequence
-            // 1: error code, 0 if OK, 1 otherwise
-            // 2, 3: acked size (includes header), LE
-            let mut read_buf = [0u8; 4];
-            if let Some(ref mut stream) = stream {
-                stream.read_exact(&mut read_buf).await?
-            } else {
-                break;
+            wait_retry(&mut self.retries, e, self.json).await?;
+            match self.reconnect().await {
+                Ok(connection) => {
+                    self.connection = connection;
+                    self.connected();
+                    return Ok(());
+                }
+                Err(err) => e = err,
+            }
+        }
+    }
+
+    async fn enter_dir(&mut self, name: &str) -> Result<(), Error> {
+        while let Err(e) = self.connection.enter_dir(name).await {
+            self.recover(e).await?;
+        }
+        self.dirs.push(name.to_string());
+        Ok(())
+    }
+
+    async fn leave_dir(&mut self) -> Result<(), Error> {
+        while let Err(e) = self.connection.leave_dir().await {
+            self.recover(e).await?;
+        }
+        self.dirs.pop();
+        Ok(())
+    }
+
+    /// Sends `data` as `name`, starting with the blocks of `prepared` if it
+    /// was compressed beforehand. Resumes at the last acknowledged block if
+    /// the connection drops. `on_progress` is called after every step, along
+    /// with what went over the wire in it
+    async fn send(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        mut prepared: Option<Pipeline>,
+        on_progress: impl Fn(&Progress, &[UploadEvent]),
+    ) -> Result<Progress, Error> {
+        let mut acked: Option<Progress> = None;
+        loop {
+            let result = match (&acked, prepared.take()) {
+                (Some(progress), _) => self.connection.resume(name, data.to_vec(), progress.clone()).await,
+                (None, Some(pipeline)) => Ok(self.connection.send(pipeline)),
+                (None, None) => self.connection.upload(name, data.to_vec()),
+            };
+            let e = match result {
+                Ok(mut upload) => loop {
+                    let result = upload.next().await;
+                    on_progress(upload.progress(), upload.events());
+                    match result {
+                        Ok(Some(_)) => {}
+                        Ok(None) => return Ok(upload.progress().clone()),
+                        Err(e) => {
+                            acked = Some(upload.progress().clone());
+                            break e;
+                        }
+                    }
+                },
+                Err(e) => e,
             };
-            if read_buf[0] != seq {
-                info!("Got out of order ACK: {} instead of {}", read_buf[0], seq);
+            self.recover(e).await?;
+        }
+    }
+}
+
+async fn transmit(
+    uploader: Uploader,
+    retries: usize,
+    json: bool,
+    run: Option<Option<&str>>,
+    items: Vec<Item>,
+    summaries: &mut Vec<Summary>,
+) -> Result<(), Error> {
+    // Start compressing the first file while connecting
+    let mut prepared = items.iter()
+        .find_map(|item| match item {
+            Item::File(Entry { name, data: Ok(data), .. }) => Some(uploader.prepare(name, data.clone())),
+            _ => None,
+        })
+        .and_then(Result::ok);
+    let pacing = uploader.pacing;
+    let mut transfer = Transfer::connect(uploader, retries, json).await?;
+
+    let sizes = items.iter().filter_map(|item| match item {
+        Item::File(entry) => entry.data.as_ref().ok().map(Vec::len),
+        _ => None,
+    });
+    // Whole transfer, for the ETA
+    let mut all = Progress {
+        blocks_num: sizes.clone().map(|size| size.div_ceil(CHUNK_SIZE)).sum(),
+        total_bytes: sizes.sum(),
+        ..Default::default()
+    };
+    // Goes along with the log
+    let bar = if log_enabled!(Level::Info) { ProgressBar::new(all.total_bytes as u64) } else { ProgressBar::hidden() };
+    // ETA of indicatif only knows the speed so far, not the pacing
+    bar.set_style(ProgressStyle::with_template("{msg:12} [{wide_bar}] {bytes}/{total_bytes} {prefix}")
+        .unwrap());
+    let started = time::Instant::now();
+
+    let mut items = items.into_iter();
+    while let Some(item) = items.next() {
+        let entry = match item {
+            Item::File(entry) => entry,
+            Item::EnterDir { path, name } => {
+                info!("Entering {} as {}", path, name);
+                if let Err(e) = transfer.enter_dir(&name).await {
+                    bar.abandon();
+                    transfer.record(summaries, Summary { path, name, result: Err((&e).into()) });
+                    transfer.skip_rest(items, summaries);
+                    return Ok(());
+                }
+                continue;
+            }
+            Item::LeaveDir { path } => {
+                if let Err(e) = transfer.leave_dir().await {
+                    bar.abandon();
+                    transfer.record(summaries, Summary { path, name: String::new(), result: Err((&e).into()) });
+                    transfer.skip_rest(items, summaries);
+                    return Ok(());
+                }
                 continue;
             }
-            acked += read_buf[2] as usize + read_buf[3] as usize * 256;
-            if acked == block.len() {
-                break;
+        };
+        let data = match entry.data {
+            Ok(data) => data,
+            Err(e) => {
+                transfer.record(summaries, Summary { path: entry.path, name: entry.name, result: Err(Cause::file(e)) });
+                continue;
+            }
+        };
+
+        info!("Sending {} as {}", entry.path, entry.name);
+        if json {
+            emit(&Event::FileStarted { path: &entry.path, name: &entry.name, size: data.len() });
+        }
+        bar.set_message(entry.name.clone());
+        let result = transfer.send(&entry.name, &data, prepared.take(), |progress, events| {
+            bar.set_position((all.sent_bytes + progress.sent_bytes) as u64);
+            let so_far = Progress {
+                current_block: all.current_block + progress.current_block,
+                sent_bytes: all.sent_bytes + progress.sent_bytes,
+                compressed_bytes: all.compressed_bytes + progress.compressed_bytes,
+                ..all.clone()
+            };
+            if let Some(eta) = so_far.eta(started.elapsed(), &pacing) {
+                bar.set_prefix(format!("{:#}", HumanDuration(eta)));
+            }
+            if json {
+                for event in events {
+                    emit(&event.into());
+                }
+                emit(&Event::Progress { status: progress.into() });
+            }
+        }).await;
+        // Whether it was sent or not, like the bar does
+        all.sent_bytes += data.len();
+        all.current_block += data.len().div_ceil(CHUNK_SIZE);
+
+        match result {
+            Ok(progress) => {
+                all.compressed_bytes += progress.compressed_bytes;
+                let result = match run {
+                    Some(command) => {
+                        // Under the name it was saved as, it may be renamed
+                        let name = progress.decision.map_or(entry.name.clone(), |decision| {
+                            decision.saved_as(&entry.name, transfer.uploader.long_names)
+                        });
+                        transfer.run(&name, command).await.map(|()| progress)
+                    }
+                    None => Ok(progress),
+                };
+                transfer.record(summaries, Summary { path: entry.path, name: entry.name, result });
+            }
+            Err(e @ Error::Name(..)) => {
+                transfer.record(summaries, Summary { path: entry.path, name: entry.name, result: Err((&e).into()) });
+            }
+            Err(e) => {
+                // Receiver gives up on errors, so the rest can't be sent
+                bar.abandon();
+                transfer.record(summaries, Summary { path: entry.path, name: entry.name, result: Err((&e).into()) });
+                transfer.skip_rest(items, summaries);
+                return Ok(());
             }
         }
     }
     bar.finish();
 
+    Ok(())
+}
+
+/// Reads `filenames` and sends them, returns the outcome of every file
+async fn send_files(
+    args: &Arguments,
+    filenames: &[String],
+    address: &Address,
+    destination: &Destination,
+) -> Result<Vec<Summary>, Error> {
+    let (mut items, mut summaries) = read_items(filenames, args.recursive, args.long_names).await;
+    let mut failed = false;
+    match &mut items[..] {
+        [Item::File(entry)] => {
+            if let Some(name) = &args.rename {
+                entry.name = name.clone();
+            }
+            // Checked before connecting, as soon as the name is known
+            if let Some(Err(e)) = args.run.as_ref().map(|command| run_command(&entry.name, command.as_deref())) {
+                summaries.push(Summary { path: entry.path.clone(), name: entry.name.clone(), result: Err((&e).into()) });
+                failed = true;
+            }
+        }
+        _ if args.rename.is_some() || args.run.is_some() => {
+            let option = if args.rename.is_some() { "--as" } else { "--run" };
+            let e = Cause::file(format!("{} needs a single file to send", option));
+            summaries.push(Summary { path: String::new(), name: String::new(), result: Err(e) });
+            failed = true;
+        }
+        _ => {}
+    }
+    if args.json {
+        for summary in &summaries {
+            summary.emit(args.long_names);
+        }
+    }
+    if failed {
+        return Ok(summaries);
+    }
+    if args.dummy && !args.json {
+        print_layout(&items, destination);
+    }
+
+    let uploader = Uploader {
+        address: address.clone(),
+        compression: if args.no_compression { Compression::None } else { args.compression },
+        use_crc: args.crc,
+        dummy: args.dummy,
+        timeouts: Timeouts::from_secs(args.connect_timeout, args.write_timeout, args.ack_timeout),
+        window: args.window,
+        long_names: args.long_names,
+        destination: destination.clone(),
+        collision: args.collision,
+        pacing: Pacing::from_rate_and_millis(args.rate, args.delay),
+    };
+
+    let run = args.run.as_ref().map(Option::as_deref);
+    transmit(uploader, args.retries, args.json, run, items, &mut summaries).await?;
+    Ok(summaries)
+}
+
+/// Logs the outcome of every file and the totals, returns true if all the
+/// files were sent
+fn report(args: &Arguments, summaries: &[Summary], elapsed: Duration) -> bool {
+    let mut total_bytes = 0;
+    let mut compressed_bytes = 0;
+    let mut quick_saved = 0;
+    let mut optimal_saved = 0;
+    let mut failed = 0;
+    for summary in summaries {
+        match &summary.result {
+            Ok(progress) => {
+                info!(
+                    "{} -> {}{}: compressed {} bytes into {} bytes, ratio: {}{}{}",
+                    summary.path,
+                    progress.decision.map_or(summary.name.clone(), |decision| {
+                        decision.saved_as(&summary.name, args.long_names)
+                    }),
+                    progress.decision.map_or(String::new(), |decision| format!(" ({})", decision)),
+                    progress.total_bytes,
+                    progress.compressed_bytes,
+                    progress.compressed_bytes as f32 / progress.total_bytes as f32,
+                    progress.crc.map_or(String::new(), |crc| format!(", CRC: {:#06x}", crc)),
+                    if progress.committed { ", committed" } else { "" },
+                );
+                total_bytes += progress.total_bytes;
+                compressed_bytes += progress.compressed_bytes;
+                quick_saved += progress.quick_saved;
+                optimal_saved += progress.optimal_saved;
+            }
+            Err(e) if summary.path.is_empty() => {
+                error!("{}", e);
+                failed += 1;
+            }
+            Err(e) => {
+                error!("{}: {}", summary.path, e);
+                failed += 1;
+            }
+        }
+    }
+
     info!(
-        "Compressed {} bytes into {} bytes, ratio: {}, elapsed: {:.2?}",
+        "Sent {} of {} files, compressed {} bytes into {} bytes, ratio: {}, elapsed: {:.2?}",
+        summaries.len() - failed,
+        summaries.len(),
         total_bytes,
         compressed_bytes,
         compressed_bytes as f32 / total_bytes as f32,
-        now.elapsed()
+        elapsed
     );
-    Ok(())
-}
-
-fn split_at_last_dot(filename: &str) -> (String, String) {
-    let parts: Vec<&str> = filename.split('.').collect();
-
-    if parts.len() <= 1 {
-        return (filename.to_string(), "".to_string());
+    if quick_saved + optimal_saved > 0 {
+        info!("Quick mode saved {} bytes, optimal mode saved {} bytes", quick_saved, optimal_saved);
+    }
+    if args.json {
+        emit(&Event::Summary {
+            sent: summaries.len() - failed,
+            failed,
+            total_bytes,
+            compressed_bytes,
+            quick_saved,
+            optimal_saved,
+            elapsed: elapsed.as_secs_f64(),
+        });
     }
 
-    let last = parts.last().unwrap().to_string();
-    let first = parts[..parts.len() - 1].join(" ");
+    failed == 0
+}
 
-    (first, last)
+/// Returns true if all the files were sent
+async fn process(args: Arguments, address: Address, destination: Destination) -> Result<bool, Error> {
+    let now = time::Instant::now();
+    let summaries = send_files(&args, &args.filenames, &address, &destination).await?;
+    Ok(report(&args, &summaries, now.elapsed()))
 }
 
-fn filename_to_short(filename: &str) -> String {
-    let (mut name, mut extension) = split_at_last_dot(filename);
+/// Prints a single line with the files sent in the round and the ones
+/// that failed
+fn print_round(round: usize, args: &Arguments, result: &Result<Vec<Summary>, Error>, elapsed: Duration) {
+    let summaries = match result {
+        Ok(summaries) => summaries,
+        Err(e) => {
+            println!("#{} failed: {}", round, e);
+            return;
+        }
+    };
+    let mut sent = Vec::new();
+    let mut failed = Vec::new();
+    let mut total_bytes = 0;
+    let mut compressed_bytes = 0;
+    for summary in summaries {
+        match &summary.result {
+            Ok(progress) => {
+                sent.push(progress.decision.map_or(summary.name.clone(), |decision| {
+                    decision.saved_as(&summary.name, args.long_names)
+                }));
+                total_bytes += progress.total_bytes;
+                compressed_bytes += progress.compressed_bytes;
+            }
+            Err(e) if summary.path.is_empty() => failed.push(e.to_string()),
+            Err(e) => failed.push(format!("{}: {}", summary.path, e)),
+        }
+    }
 
-    name = name.chars().map(|c| { if c.is_ascii() { c.to_uppercase().next().unwrap_or('_') } else { '_' } }).collect();
+    let mut line = format!("#{}", round);
+    if !sent.is_empty() {
+        line += &format!(" sent {}, {} bytes, {} on the wire, {:.2?}",
+            sent.join(", "), total_bytes, compressed_bytes, elapsed);
+    }
+    if !failed.is_empty() {
+        line += &format!("{} failed {}", if sent.is_empty() { "" } else { ";" }, failed.join(", "));
+    }
+    println!("{}", line);
+}
 
-    if name.len() > 8 {
-        name = name
-                .chars()
-                .take(8)
-                .collect();
+/// Sends the files, then sends them again whenever they change, connecting
+/// anew every time. Runs until it's interrupted
+async fn watch(args: Arguments, address: Address, destination: Destination) -> Result<bool, Error> {
+    let mut paths = Vec::new();
+    for path in expand(&args.filenames) {
+        match path {
+            Ok(path) => paths.push(path),
+            Err(e) => error!("{}", e),
+        }
+    }
+    let mut watcher = Watcher::new(paths.iter().map(PathBuf::from), DEFAULT_DEBOUNCE);
+    if !args.json {
+        println!("Watching {} files, press Ctrl+C to stop", paths.len());
     }
 
-    extension = extension.chars().map(|c| { if c.is_ascii() { c.to_uppercase().next().unwrap_or('_') } else { '_' } }).collect();
+    let mut round = 0;
+    loop {
+        round += 1;
+        let now = time::Instant::now();
+        let result = send_files(&args, &paths, &address, &destination).await;
+        let elapsed = now.elapsed();
+        match &result {
+            Ok(summaries) if args.json => {
+                report(&args, summaries, elapsed);
+            }
+            Err(e) if args.json => emit(&Event::Error { path: "", name: "", cause: &e.into() }),
+            _ => print_round(round, &args, &result, elapsed),
+        }
 
-    if extension.len() > 3 {
-        extension = extension.chars().take(3).collect();
+        paths = watcher.changed().await.iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
     }
+}
 
-    let re = Regex::new(r"[ \t\.\\/]").unwrap();
+/// Asks whether to scan anyway, as it ends the older dot commands around.
+/// There's no one to answer without a terminal, --yes is needed then.
+fn confirm_scan() -> bool {
+    warn!("Dot commands older than v0.5 quit when probed, even in the middle of a transfer");
+    if !std::io::stdin().is_terminal() {
+        error!("Not scanning without --yes");
+        return false;
+    }
+    print!("Scan anyway? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
 
-    name = re.replace_all(&name, "_").to_string();
-    extension = re.replace_all(&extension, "_").to_string();
+/// Lists the Spectrums found on the local network, returns their addresses
+async fn discover(port: u16, timeout: u64) -> Vec<String> {
+    info!("Looking for ZX Spectrums running zxmit");
+    let found = match zxmit_protocol::discover(port, Duration::from_secs(timeout)).await {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
 
-    std::format!("{}.{}", name, extension)
+    let hosts: Vec<String> = found.iter()
+        .map(|addr| if addr.port() == DEFAULT_PORT { addr.ip().to_string() } else { addr.to_string() })
+        .collect();
+    match &hosts[..] {
+        [] => error!("No ZX Spectrum found, make sure zxmit is running and it's on the same network"),
+        [host] => info!("Found {}", host),
+        hosts => {
+            for host in hosts {
+                info!("Found {}", host);
+            }
+        }
+    }
+    hosts
 }
 
-async fn process(args: Arguments) -> std::io::Result<()> {
-    let file = tokio::fs::read(args.filename.clone()).await?;
-    let filename = args.filename.clone();
-    let path = Path::new(&filename);
-    let basename = String::from(path.file_name().unwrap().to_str().unwrap());
-    let namebuf: Vec<u8> = filename_to_short(&basename).into();
-
-    info!(
-        "Short filaname will be {}",
-        String::from_utf8(namebuf.clone()).unwrap()
-    );
-    assert!(namebuf.len() <= 12);
+/// Prints how `path` would be sent, returns false if it can't be read
+async fn analyze(path: String, compression: Compression, use_crc: bool, long_names: bool) -> bool {
+    let entry = read_entry(path, long_names).await;
+    let data = match entry.data {
+        Ok(data) => data,
+        Err(e) => {
+            error!("{}: {}", entry.path, e);
+            return false;
+        }
+    };
+    let report = Report::analyze(&entry.name, &data, compression, use_crc, long_names);
+    let uncompressed = Report::analyze(&entry.name, &data, Compression::None, use_crc, long_names);
 
-    transmit(args.ip, namebuf, file, args.dummy, args.no_compression).await?;
+    println!("{} as {}, {} bytes", entry.path, entry.name, report.total_bytes);
+    println!("Block   Raw  Wire  Payload");
+    if let Some(block) = &report.opening {
+        let opens = if long_names { "long name" } else { "empty file" };
+        println!(" name  {:4}  {:4}  {}", block.raw_len, block.wire_len, opens);
+    }
+    for block in &report.blocks {
+        let payload = match block.raw_reason {
+            Some(reason) => format!("raw, {}", reason),
+            None => format!("{}, {} bytes", block.compressed_with, block.payload_len),
+        };
+        println!("{:5}  {:4}  {:4}  {}", block.index, block.raw_len, block.wire_len, payload);
+    }
+    println!("  end  {:4}  {:4}  file size", report.ending.raw_len, report.ending.wire_len);
 
-    Ok(())
+    println!();
+    println!("{} blocks, {} bytes of payload, {} bytes of headers, lengths and CRCs, {} bytes of ACKs",
+        report.blocks.len(), report.payload_bytes(), report.overhead_bytes(), report.ack_bytes());
+    println!("{} bytes on the wire, {} without compression, ratio: {}",
+        report.wire_bytes(), uncompressed.wire_bytes(), report.wire_bytes() as f32 / uncompressed.wire_bytes() as f32);
+    if compression != Compression::None {
+        println!("Sent raw: {} blocks that don't get smaller, {} partial blocks",
+            report.raw_blocks(RawReason::NotSmaller), report.raw_blocks(RawReason::Partial));
+    }
+    println!("Estimated transfer time, one block at a time:");
+    for driver in &Driver::ALL {
+        println!("  {}: {:.1?}, {:.1?} without compression",
+            driver, report.estimate(driver), uncompressed.estimate(driver));
+    }
+    true
 }
 
 #[tokio::main]
 async fn main() {
-    println!(
-        "zxmit {} (c) Alex Nihirash & Vasily Khoruzhick",
-        CARGO_PKG_VERSION.unwrap_or("dev")
-    );
-    let args = Arguments::parse();
+    let mut args = Arguments::parse();
+    if !args.json {
+        println!(
+            "zxmit {} (c) Alex Nihirash & Vasily Khoruzhick",
+            CARGO_PKG_VERSION.unwrap_or("dev")
+        );
+    }
+    // Discovery logs before the files are sent
+    let mut logging = false;
+    if let Some(Command::Discover { port, timeout, yes, filenames }) = args.command.take() {
+        SimpleLogger::new().init().unwrap();
+        logging = true;
+        if !yes && !confirm_scan() {
+            std::process::exit(1);
+        }
+        let hosts = discover(port, timeout).await;
+        if filenames.is_empty() {
+            std::process::exit(if hosts.is_empty() { 1 } else { 0 });
+        }
+        match &hosts[..] {
+            [host] => {
+                // Goes on like the files were given with the address
+                args.address = Some(host.clone());
+                args.filenames = filenames;
+                args.port = port;
+            }
+            [] => std::process::exit(1),
+            hosts => {
+                error!("Found {} ZX Spectrums, give the address of the one to send the files to", hosts.len());
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(Command::Analyze { file, compression, crc, long_names }) = args.command {
+        SimpleLogger::new().init().unwrap();
+        std::process::exit(if analyze(file, compression, crc, long_names).await { 0 } else { 1 });
+    }
+
+    let host = args.address.clone().unwrap_or_default();
+    let address = match Address::parse(&host, args.port) {
+        Ok(address) => address,
+        Err(e) => Arguments::command()
+            .error(ErrorKind::ValueValidation, format!("invalid address {:?}: {}", host, e))
+            .exit(),
+    };
 
-    SimpleLogger::new().init().unwrap();
+    // Checked before connecting, the Spectrum would only report a failure
+    let destination = match Destination::parse(args.dest.as_deref().unwrap_or_default(), args.long_names) {
+        Ok(destination) => destination,
+        Err(e) => Arguments::command()
+            .error(ErrorKind::ValueValidation, format!("invalid destination: {}", e))
+            .exit(),
+    };
+    if let Some(name) = &args.rename {
+        match remote_name(name, args.long_names) {
+            Ok(name) => args.rename = Some(name),
+            Err(e) => Arguments::command()
+                .error(ErrorKind::ValueValidation, format!("invalid name: {}", e))
+                .exit(),
+        }
+    } else if args.filenames.iter().any(|path| path == STDIN) {
+        Arguments::command()
+            .error(ErrorKind::MissingRequiredArgument, "reading the standard input needs --as NAME")
+            .exit();
+    }
+    if args.watch && args.filenames.iter().any(|path| path == STDIN) {
+        Arguments::command()
+            .error(ErrorKind::ArgumentConflict, "the standard input can't be watched")
+            .exit();
+    }
 
-    match process(args).await {
-        Err(e) => error!("{}", e.to_string()),
-        _ => info!("Done!"),
+    // Log goes to the standard output too and would break the JSON lines
+    let json = args.json;
+    if !json && !logging {
+        // Only the warnings in between the lines of the uploads
+        let level = if args.watch { LevelFilter::Warn } else { LevelFilter::Info };
+        SimpleLogger::new().with_level(level).init().unwrap();
+    }
+
+    let result = if args.watch {
+        watch(args, address, destination).await
+    } else {
+        process(args, address, destination).await
+    };
+    match result {
+        Err(e) => {
+            if json {
+                emit(&Event::Error { path: "", name: "", cause: &(&e).into() });
+            }
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        Ok(false) => std::process::exit(1),
+        Ok(true) => info!("Done!"),
     }
 }
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use crate::collision::Decision;
+
+/// Size of an ACK on the wire.
+pub const ACK_LEN: usize = 4;
+
+pub const ERROR_OK: u8 = 0;
+/// Packet size doesn't match the header.
+pub const ERROR_SIZE_MISMATCH: u8 = 1;
+/// Short header received before the file was opened.
+pub const ERROR_NOT_OPENED: u8 = 2;
+pub const ERROR_OPEN: u8 = 3;
+pub const ERROR_WRITE: u8 = 4;
+/// Failed to create or enter the directory.
+pub const ERROR_DIRECTORY: u8 = 5;
+/// Failed to reopen the file or seek to the offset to resume.
+pub const ERROR_RESUME: u8 = 6;
+/// CRC of the received data doesn't match the one in the block.
+pub const ERROR_CRC: u8 = 7;
+/// File exists and the collision policy doesn't allow to replace it, or
+/// all the alternative names are taken too.
+pub const ERROR_EXISTS: u8 = 8;
+/// Command to run doesn't fit into the buffer.
+pub const ERROR_RUN: u8 = 9;
+/// File size doesn't match the one in the end block.
+pub const ERROR_FILE_SIZE: u8 = 10;
+
+/// Codes from here up aren't errors, but the decision on the file opened
+/// in the packets acknowledged.
+pub const STATUS_CREATED: u8 = 0x80;
+pub const STATUS_OVERWRITTEN: u8 = 0x81;
+/// File was written out in full and closed, the reply to the end block.
+pub const STATUS_COMMITTED: u8 = 0x82;
+/// File was renamed to `NAME~N`, N is added to the code.
+pub const STATUS_RENAMED: u8 = 0x90;
+
+/// Failure reported by the receiver in the ACK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckError {
+    SizeMismatch,
+    NotOpened,
+    Open,
+    Write,
+    Directory,
+    Resume,
+    Crc,
+    Exists,
+    Run,
+    FileSize,
+    Unknown(u8),
+}
+
+impl AckError {
+    pub fn from_code(code: u8) -> Option<AckError> {
+        match code {
+            ERROR_OK => None,
+            code if code >= STATUS_CREATED => None,
+            ERROR_SIZE_MISMATCH => Some(AckError::SizeMismatch),
+            ERROR_NOT_OPENED => Some(AckError::NotOpened),
+            ERROR_OPEN => Some(AckError::Open),
+            ERROR_WRITE => Some(AckError::Write),
+            ERROR_DIRECTORY => Some(AckError::Directory),
+            ERROR_RESUME => Some(AckError::Resume),
+            ERROR_CRC => Some(AckError::Crc),
+            ERROR_EXISTS => Some(AckError::Exists),
+            ERROR_RUN => Some(AckError::Run),
+            ERROR_FILE_SIZE => Some(AckError::FileSize),
+            code => Some(AckError::Unknown(code)),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            AckError::SizeMismatch => ERROR_SIZE_MISMATCH,
+            AckError::NotOpened => ERROR_NOT_OPENED,
+            AckError::Open => ERROR_OPEN,
+            AckError::Write => ERROR_WRITE,
+            AckError::Directory => ERROR_DIRECTORY,
+            AckError::Resume => ERROR_RESUME,
+            AckError::Crc => ERROR_CRC,
+            AckError::Exists => ERROR_EXISTS,
+            AckError::Run => ERROR_RUN,
+            AckError::FileSize => ERROR_FILE_SIZE,
+            AckError::Unknown(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for AckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AckError::SizeMismatch => write!(f, "packet size doesn't match the header"),
+            AckError::NotOpened => write!(f, "file is not opened"),
+            AckError::Open => write!(f, "failed to open the file"),
+            AckError::Write => write!(f, "failed to write the file"),
+            AckError::Directory => write!(f, "failed to create or enter the directory"),
+            AckError::Resume => write!(f, "failed to resume the file"),
+            AckError::Crc => write!(f, "CRC mismatch, data got corrupted"),
+            AckError::Exists => write!(f, "file exists"),
+            AckError::Run => write!(f, "command to run is too long"),
+            AckError::FileSize => write!(f, "file size doesn't match, some of the data is missing"),
+            AckError::Unknown(code) => write!(f, "unknown error {}", code),
+        }
+    }
+}
+
+/// Acknowledgement sent by the receiver for every chunk of data the ESP
+/// delivered to it. A chunk may hold several packets or a part of one, so
+/// the sender counts the acked bytes against the packets it sent.
+///
+/// ACK is:
+/// 0: sequence of the packet the chunk ends in, or of the failed one
+/// 1: error code, `ERROR_OK` if OK, or a status code from `STATUS_CREATED`
+///    up
+/// 2, 3: acked size (includes headers), LE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ack {
+    pub seq: u8,
+    pub error: u8,
+    pub size: u16,
+}
+
+impl Ack {
+    pub fn decode(buf: &[u8; ACK_LEN]) -> Ack {
+        Ack {
+            seq: buf[0],
+            error: buf[1],
+            size: u16::from_le_bytes([buf[2], buf[3]]),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; ACK_LEN] {
+        let size = self.size.to_le_bytes();
+        [self.seq, self.error, size[0], size[1]]
+    }
+
+    pub fn failure(&self) -> Option<AckError> {
+        AckError::from_code(self.error)
+    }
+
+    /// Decision on the file opened in the packets acknowledged, if the
+    /// client chose a collision policy.
+    pub fn decision(&self) -> Option<Decision> {
+        match self.error {
+            STATUS_CREATED => Some(Decision::Created),
+            STATUS_OVERWRITTEN => Some(Decision::Overwritten),
+            code if (STATUS_RENAMED + 1..=STATUS_RENAMED + 9).contains(&code) => {
+                Some(Decision::Renamed(code - STATUS_RENAMED))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the file ended by the packets acknowledged is written out in
+    /// full.
+    pub fn committed(&self) -> bool {
+        self.error == STATUS_COMMITTED
+    }
+
+    /// Status code reporting `decision`.
+    pub fn status(decision: Decision) -> u8 {
+        match decision {
+            Decision::Created => STATUS_CREATED,
+            Decision::Overwritten => STATUS_OVERWRITTEN,
+            Decision::Renamed(n) => STATUS_RENAMED + n,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let ack = Ack::decode(&[7, ERROR_OK, 0x15, 0x04]);
+        assert_eq!(ack, Ack { seq: 7, error: ERROR_OK, size: 0x0415 });
+        assert_eq!(Ack::decode(&ack.encode()), ack);
+        assert_eq!((ack.failure(), ack.decision(), ack.committed()), (None, None, false));
+    }
+
+    #[test]
+    fn failures() {
+        assert_eq!(Ack::decode(&[1, ERROR_OPEN, 4, 0]).failure(), Some(AckError::Open));
+        assert_eq!(Ack::decode(&[1, 0x42, 4, 0]).failure(), Some(AckError::Unknown(0x42)));
+        for code in ERROR_SIZE_MISMATCH..=ERROR_FILE_SIZE {
+            assert_eq!(AckError::from_code(code).map(|error| error.code()), Some(code));
+        }
+    }
+
+    #[test]
+    fn statuses() {
+        for decision in [Decision::Created, Decision::Overwritten, Decision::Renamed(1), Decision::Renamed(9)] {
+            let ack = Ack { seq: 0, error: Ack::status(decision), size: 17 };
+            assert_eq!((ack.failure(), ack.decision()), (None, Some(decision)));
+        }
+        assert_eq!(Ack::decode(&[0, STATUS_RENAMED, 0, 0]).decision(), None);
+
+        let ack = Ack::decode(&[3, STATUS_COMMITTED, 8, 0]);
+        assert_eq!((ack.failure(), ack.decision(), ack.committed()), (None, None, true));
+    }
+}
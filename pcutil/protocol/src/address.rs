@@ -0,0 +1,126 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use crate::DEFAULT_PORT;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    Empty,
+    /// Address has a port, but no host, e.g. ":6144".
+    NoHost(String),
+    /// Host contains a character that can't be a part of an address.
+    Host(String, char),
+    /// Port isn't a number in 1..=65535 range.
+    Port(String),
+    /// Brackets around IPv6 address are not closed or followed by
+    /// something other than a port.
+    Brackets(String),
+    /// Address looks like IPv6 literal, but is not a valid one.
+    Ipv6(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::Empty => write!(f, "address is empty"),
+            AddressError::NoHost(address) => write!(f, "address {:?} has no host", address),
+            AddressError::Host(host, c) => write!(f, "host {:?} contains invalid character {:?}", host, c),
+            AddressError::Port(port) => write!(f, "port {:?} is not a number between 1 and 65535", port),
+            AddressError::Brackets(address) => write!(f, "address {:?} must be in [IPv6]:port form", address),
+            AddressError::Ipv6(host) => write!(f, "{:?} is not a valid IPv6 address", host),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Parses a port number, rejecting port 0.
+pub fn parse_port(port: &str) -> Result<u16, AddressError> {
+    match port.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(AddressError::Port(port.to_string())),
+    }
+}
+
+/// Address of the receiver in `host[:port]` form, where host is a host
+/// name, IPv4 address or IPv6 address in brackets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Address {
+    /// Parses `host` or `host:port`, `default_port` is used if the port is
+    /// omitted.
+    pub fn parse(address: &str, default_port: u16) -> Result<Address, AddressError> {
+        let address = address.trim();
+        if address.is_empty() {
+            return Err(AddressError::Empty);
+        }
+
+        if let Some(rest) = address.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']')
+                .ok_or_else(|| AddressError::Brackets(address.to_string()))?;
+            let port = match rest {
+                "" => default_port,
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => parse_port(port)?,
+                    None => return Err(AddressError::Brackets(address.to_string())),
+                },
+            };
+            return Self::ipv6(host, port);
+        }
+
+        // More than one colon, can only be IPv6 address without a port
+        if address.matches(':').count() > 1 {
+            return Self::ipv6(address, default_port);
+        }
+
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (address, default_port),
+        };
+
+        if host.is_empty() {
+            return Err(AddressError::NoHost(address.to_string()));
+        }
+        if let Some(c) = host.chars().find(|c| !(c.is_alphanumeric() || *c == '.' || *c == '-' || *c == '_')) {
+            return Err(AddressError::Host(host.to_string(), c));
+        }
+
+        Ok(Address {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    fn ipv6(host: &str, port: u16) -> Result<Address, AddressError> {
+        match host.parse::<Ipv6Addr>() {
+            Ok(_) => Ok(Address {
+                host: host.to_string(),
+                port,
+            }),
+            Err(_) => Err(AddressError::Ipv6(host.to_string())),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<Address, AddressError> {
+        Address::parse(address, DEFAULT_PORT)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::ack::ACK_LEN;
+use crate::block::{Block, BlockBuilder, CHUNK_SIZE, pays_off};
+use crate::compression::Compression;
+use crate::crc::CRC_INIT;
+
+/// Bits on the UART per byte, 8N1.
+const UART_BITS_PER_BYTE: u32 = 10;
+/// `+IPD,0,1045:` the ESP puts in front of every frame, with the line break.
+const IPD_LEN: usize = 16;
+/// UART traffic of sending an ACK: `AT+CIPSEND=0,4`, the ESP prompt, the ACK
+/// itself and the ESP reports.
+const ACK_EXCHANGE_LEN: usize = 57;
+/// T-states `dzx0_standard` takes per unpacked byte, roughly.
+const DZX0_T_STATES: u64 = 30;
+/// T-states `ldir` takes per byte of an uncompressed block.
+const COPY_T_STATES: u64 = 21;
+/// T-states `Wifi.checkCrc` takes per byte.
+const CRC_T_STATES: u64 = 175;
+
+/// UART driver the dot command is built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Driver {
+    pub name: &'static str,
+    /// Speed of the UART between the ESP and the Spectrum.
+    pub baud: u32,
+    /// CPU clock the dot command runs at.
+    pub cpu_hz: u32,
+}
+
+impl Driver {
+    /// UART bit-banged through the AY ports, see `drivers/ay.asm`.
+    pub const AY: Driver = Driver { name: "AY", baud: 9600, cpu_hz: 3_500_000 };
+    pub const ZX_UNO: Driver = Driver { name: "ZX-Uno", baud: 115_200, cpu_hz: 3_500_000 };
+    /// Next runs the dot command at 14 MHz.
+    pub const NEXT: Driver = Driver { name: "Next", baud: 115_200, cpu_hz: 14_000_000 };
+
+    pub const ALL: [Driver; 3] = [Driver::AY, Driver::ZX_UNO, Driver::NEXT];
+}
+
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} baud, {} MHz)", self.name, self.baud, self.cpu_hz as f32 / 1e6)
+    }
+}
+
+/// Why a block is sent uncompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawReason {
+    /// Compression is turned off.
+    Disabled,
+    /// Compressed data isn't any smaller.
+    NotSmaller,
+    /// Compression of a partial block doesn't pay off the length sent along.
+    Partial,
+}
+
+impl fmt::Display for RawReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawReason::Disabled => write!(f, "compression is off"),
+            RawReason::NotSmaller => write!(f, "doesn't get smaller"),
+            RawReason::Partial => write!(f, "partial block, the length costs more than compression saves"),
+        }
+    }
+}
+
+/// How a single block of the file goes over the wire.
+#[derive(Debug, Clone)]
+pub struct BlockReport {
+    pub index: usize,
+    /// Amount of file data in the block.
+    pub raw_len: usize,
+    pub payload_len: usize,
+    /// Size of the block on the wire, including the header.
+    pub wire_len: usize,
+    /// Mode the payload was compressed in, `Compression::None` if it wasn't.
+    pub compressed_with: Compression,
+    /// Why the block is sent as is, `None` if it's compressed.
+    pub raw_reason: Option<RawReason>,
+    /// Whether the block carries CRC the receiver checks.
+    pub crc: bool,
+}
+
+impl BlockReport {
+    fn new(index: usize, block: &Block, raw_reason: Option<RawReason>) -> BlockReport {
+        BlockReport {
+            index,
+            raw_len: block.raw_len,
+            payload_len: block.payload.len(),
+            wire_len: block.wire_len(),
+            compressed_with: block.compressed_with,
+            raw_reason,
+            crc: block.crc.is_some(),
+        }
+    }
+
+    /// Rough time `driver` takes to receive and process the block.
+    pub fn estimate(&self, driver: &Driver) -> Duration {
+        let uart_bytes = (self.wire_len + IPD_LEN + ACK_EXCHANGE_LEN) as u64;
+        let uart = Duration::from_secs_f64((uart_bytes * UART_BITS_PER_BYTE as u64) as f64 / driver.baud as f64);
+        let per_byte = if self.raw_reason.is_none() { DZX0_T_STATES } else { COPY_T_STATES };
+        let crc = if self.crc { CRC_T_STATES } else { 0 };
+        let t_states = self.raw_len as u64 * (per_byte + crc);
+        uart + Duration::from_secs_f64(t_states as f64 / driver.cpu_hz as f64)
+    }
+}
+
+/// Offline report of how a file would be sent: how every block compresses,
+/// the bytes on the wire and estimated transfer times.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Block that opens the file with a long name, or an empty file, ahead
+    /// of the data.
+    pub opening: Option<BlockReport>,
+    pub blocks: Vec<BlockReport>,
+    /// Block that ends the file once the data is acknowledged.
+    pub ending: BlockReport,
+    pub total_bytes: usize,
+}
+
+impl Report {
+    /// Splits `data` into blocks like an upload of it as `name` would,
+    /// without any networking communication.
+    pub fn analyze(name: &str, data: &[u8], compression: Compression, use_crc: bool, long_names: bool) -> Report {
+        let (mut builder, opening) = if long_names || data.is_empty() {
+            let opening = Block::open(name.as_bytes(), None).with_long_name();
+            (BlockBuilder::resume(0, compression), Some(BlockReport::new(0, &opening, None)))
+        } else {
+            (BlockBuilder::new(name.into(), compression), None)
+        };
+        if use_crc {
+            builder = builder.with_crc(CRC_INIT);
+        }
+
+        let mut blocks = Vec::new();
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let (compressed, raw_reason) = if compression == Compression::None {
+                (None, Some(RawReason::Disabled))
+            } else {
+                match compression.compress(chunk) {
+                    None => (None, Some(RawReason::NotSmaller)),
+                    Some((payload, _)) if !pays_off(chunk.len(), payload.len()) => (None, Some(RawReason::Partial)),
+                    compressed => (compressed, None),
+                }
+            };
+            let block = builder.build_compressed(chunk, compressed);
+            blocks.push(BlockReport::new(index, &block, raw_reason));
+        }
+
+        let ending = BlockReport::new(blocks.len(), &Block::end(data.len() as u32), None);
+        Report { opening, blocks, ending, total_bytes: data.len() }
+    }
+
+    /// All the blocks in the order they are sent.
+    fn all_blocks(&self) -> impl Iterator<Item = &BlockReport> {
+        self.opening.iter().chain(&self.blocks).chain([&self.ending])
+    }
+
+    pub fn payload_bytes(&self) -> usize {
+        self.all_blocks().map(|block| block.payload_len).sum()
+    }
+
+    /// Headers, lengths of partial blocks and CRCs.
+    pub fn overhead_bytes(&self) -> usize {
+        self.all_blocks().map(|block| block.wire_len - block.payload_len).sum()
+    }
+
+    /// ACKs sent back, one per block when they are sent one by one.
+    pub fn ack_bytes(&self) -> usize {
+        self.all_blocks().count() * ACK_LEN
+    }
+
+    /// Everything that goes over the connection both ways.
+    pub fn wire_bytes(&self) -> usize {
+        self.all_blocks().map(|block| block.wire_len).sum::<usize>() + self.ack_bytes()
+    }
+
+    /// Number of data blocks sent uncompressed for `reason`.
+    pub fn raw_blocks(&self, reason: RawReason) -> usize {
+        self.blocks.iter().filter(|block| block.raw_reason == Some(reason)).count()
+    }
+
+    /// Rough time `driver` takes to receive the file, one block at a time.
+    /// Doesn't include the network latency and writing to the SD card.
+    pub fn estimate(&self, driver: &Driver) -> Duration {
+        self.all_blocks().map(|block| block.estimate(driver)).sum()
+    }
+}
@@ -0,0 +1,605 @@
+use crate::collision::{Collision, POLICY_NONE};
+use crate::compression::Compression;
+use crate::crc::{CRC_LEN, crc16};
+
+/// Largest amount of file data carried by one block, before compression.
+pub const CHUNK_SIZE: usize = 1024;
+pub const SHORT_HEADER_LEN: usize = 4;
+pub const LONG_HEADER_LEN: usize = 17;
+
+/// Payload is ZX0 compressed and unpacks to exactly `CHUNK_SIZE` bytes,
+/// unless `FLAGS_LENGTH` is set.
+pub const FLAGS_COMPRESSED: u8 = 1;
+/// Header carries a zero terminated file name and the collision policy,
+/// the receiver closes the current file, if any, and opens the new one.
+pub const FLAGS_LONG_HEADER: u8 = 2;
+/// Long header carries a directory name instead: the receiver closes the
+/// current file, creates the directory if it doesn't exist and enters it.
+/// A drive letter followed by a colon selects the drive instead. The
+/// payload is empty.
+pub const FLAGS_DIRECTORY: u8 = 4;
+/// Long header of an interrupted file: the receiver closes the current file,
+/// opens the existing file without truncating it and continues writing at
+/// the offset carried in the payload as 4 bytes LE.
+pub const FLAGS_RESUME: u8 = 8;
+/// Size of the payload of a resume block, and of an end block.
+pub const RESUME_PAYLOAD_LEN: usize = 4;
+/// Block is followed by 2 bytes LE of CRC-16 of the file data so far,
+/// including the block, after decompression. The CRC of the last block is
+/// the CRC of the whole file. In a resume block it's the CRC of the data
+/// before the offset.
+pub const FLAGS_CRC: u8 = 16;
+/// Header is followed by 2 bytes LE of the length of the payload after
+/// decompression, for compressed blocks shorter than `CHUNK_SIZE`. Sent for
+/// the last block of most files, dot commands older than v0.5 reject it.
+pub const FLAGS_LENGTH: u8 = 32;
+/// Size of the decompressed length on the wire.
+pub const LENGTH_LEN: usize = 2;
+/// Short header alone asking the receiver to identify itself with an ACK,
+/// changes nothing. A connection that only probes doesn't end the receiver.
+pub const FLAGS_PROBE: u8 = 64;
+/// Name that doesn't fit into the long header ends the payload instead, the
+/// header is a short one and the name's length is what's left of the
+/// payload size. On its own the block opens the file like a long header
+/// does, but carries no data: it comes in the next blocks. The collision
+/// policy byte precedes the name then. With `FLAGS_DIRECTORY` or
+/// `FLAGS_RESUME` it names the directory or the file to resume. Only for
+/// targets that support long file names, though it opens empty files with
+/// DOS 8.3 names too, they have no data block to carry the long header.
+pub const FLAGS_LONG_NAME: u8 = 128;
+
+/// Control packet that ends the current file: the receiver writes out
+/// whatever it buffered, checks the file size against the one in the payload
+/// as 4 bytes LE, closes the file and confirms it with `STATUS_COMMITTED`.
+pub const PACKET_END: u8 = 1;
+/// Control packet carrying a dot command line without the dot in the
+/// payload, the receiver closes the current file and runs the command once
+/// the connection is closed.
+pub const PACKET_RUN: u8 = 2;
+
+/// Longest command line the receiver runs, it keeps it in a buffer of its
+/// own along with the terminator.
+pub const MAX_RUN_COMMAND_LEN: usize = 39;
+
+/// Directory name that makes the receiver go one level up.
+pub const PARENT_DIR: &str = "..";
+/// Directory name that makes the receiver go to the root of the drive.
+pub const ROOT_DIR: &str = "/";
+
+/// Block header.
+///
+/// Short header is:
+/// 0: sequence
+/// 1, 2: payload size, LE
+/// 3: flags
+///
+/// Long header is followed by:
+/// 4-15: up to 12 bytes of zero padded file name
+/// 16: collision policy, `POLICY_NONE` unless the client chose one
+///
+/// Long header of a control packet has no name, the type of the packet
+/// follows the terminator instead, e.g. `PACKET_END`. Older receivers fail
+/// to open a file without a name, rather than take it for something else.
+///
+/// Either of them is followed by the decompressed length if `FLAGS_LENGTH`
+/// is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub seq: u8,
+    pub size: u16,
+    pub flags: u8,
+    pub name: Option<Vec<u8>>,
+    /// Length of the payload after decompression, sent if `FLAGS_LENGTH`
+    /// is set.
+    pub length: Option<u16>,
+    /// What the receiver does if the file exists, sent in the long header.
+    pub policy: u8,
+    /// Type of a control packet, sent in the long header in place of the
+    /// name.
+    pub control: Option<u8>,
+}
+
+impl Header {
+    /// Size of the header on the wire.
+    pub fn encoded_len(&self) -> usize {
+        let len = if self.flags & FLAGS_LONG_HEADER != 0 {
+            LONG_HEADER_LEN
+        } else {
+            SHORT_HEADER_LEN
+        };
+        if self.flags & FLAGS_LENGTH != 0 {
+            len + LENGTH_LEN
+        } else {
+            len
+        }
+    }
+
+    /// Size of the data following the payload.
+    pub fn trailer_len(&self) -> usize {
+        if self.flags & FLAGS_CRC != 0 {
+            CRC_LEN
+        } else {
+            0
+        }
+    }
+
+    /// Size of the whole block on the wire.
+    pub fn packet_len(&self) -> usize {
+        self.encoded_len() + self.size as usize + self.trailer_len()
+    }
+
+    /// Size of the whole packet starting with `buf`, which only has to hold
+    /// its first `SHORT_HEADER_LEN` bytes. Lets the receiver find packet
+    /// boundaries in a stream.
+    pub fn peek_packet_len(buf: &[u8]) -> Option<usize> {
+        if buf.len() < SHORT_HEADER_LEN {
+            return None;
+        }
+        let header = Header {
+            seq: buf[0],
+            size: u16::from_le_bytes([buf[1], buf[2]]),
+            flags: buf[3],
+            name: None,
+            length: None,
+            policy: POLICY_NONE,
+            control: None,
+        };
+        Some(header.packet_len())
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.push(self.seq);
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.push(self.flags);
+        if self.flags & FLAGS_LONG_HEADER != 0 {
+            if let Some(control) = self.control {
+                out.extend_from_slice(&[0, control]);
+            } else if let Some(name) = &self.name {
+                out.extend_from_slice(name);
+            }
+            out.resize(start + LONG_HEADER_LEN - 1, 0);
+            out.push(self.policy);
+        }
+        if self.flags & FLAGS_LENGTH != 0 {
+            out.extend_from_slice(&self.length.unwrap_or_default().to_le_bytes());
+        }
+    }
+
+    /// Parses the header at the start of `buf`, returns `None` if `buf` is
+    /// too short to hold it.
+    pub fn parse(buf: &[u8]) -> Option<Header> {
+        if buf.len() < SHORT_HEADER_LEN {
+            return None;
+        }
+
+        let flags = buf[3];
+        let mut len = SHORT_HEADER_LEN;
+        let mut policy = POLICY_NONE;
+        let mut control = None;
+        let name = if flags & FLAGS_LONG_HEADER != 0 {
+            if buf.len() < LONG_HEADER_LEN {
+                return None;
+            }
+            let field = &buf[SHORT_HEADER_LEN..LONG_HEADER_LEN - 1];
+            let end = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+            len = LONG_HEADER_LEN;
+            policy = buf[LONG_HEADER_LEN - 1];
+            if end == 0 {
+                control = Some(field[1]);
+                None
+            } else {
+                Some(field[..end].to_vec())
+            }
+        } else {
+            None
+        };
+
+        let length = if flags & FLAGS_LENGTH != 0 {
+            if buf.len() < len + LENGTH_LEN {
+                return None;
+            }
+            Some(u16::from_le_bytes([buf[len], buf[len + 1]]))
+        } else {
+            None
+        };
+
+        Some(Header {
+            seq: buf[0],
+            size: u16::from_le_bytes([buf[1], buf[2]]),
+            flags,
+            name,
+            length,
+            policy,
+            control,
+        })
+    }
+}
+
+/// A single block ready to be written to the socket.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub header: Header,
+    pub payload: Vec<u8>,
+    /// CRC of the file so far, sent if `FLAGS_CRC` is set.
+    pub crc: Option<u16>,
+    /// Amount of file data in the block, before compression.
+    pub raw_len: usize,
+    /// Mode the payload was compressed in, `Compression::None` if it wasn't.
+    pub compressed_with: Compression,
+}
+
+impl Block {
+    pub fn seq(&self) -> u8 {
+        self.header.seq
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.header.flags & FLAGS_COMPRESSED != 0
+    }
+
+    /// Size of the block on the wire, including the header.
+    pub fn wire_len(&self) -> usize {
+        self.header.packet_len()
+    }
+
+    /// Builds a block that makes the receiver enter directory `name`.
+    pub fn directory(name: &[u8]) -> Block {
+        Block {
+            header: Header {
+                seq: 0,
+                size: 0,
+                flags: FLAGS_LONG_HEADER | FLAGS_DIRECTORY,
+                name: Some(name.to_vec()),
+                length: None,
+                policy: POLICY_NONE,
+                control: None,
+            },
+            payload: Vec::new(),
+            crc: None,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Builds a block that makes the receiver create file `name` without
+    /// writing anything into it, the data follows in the blocks numbered from
+    /// `BlockBuilder::resume(0, ..)`.
+    pub fn open(name: &[u8], collision: Option<Collision>) -> Block {
+        Block {
+            header: Header {
+                seq: 0,
+                size: 0,
+                flags: FLAGS_LONG_HEADER,
+                name: Some(name.to_vec()),
+                length: None,
+                policy: collision.map_or(POLICY_NONE, |collision| collision.code()),
+                control: None,
+            },
+            payload: Vec::new(),
+            crc: None,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Builds a block that only makes the receiver acknowledge it.
+    pub fn probe() -> Block {
+        Block {
+            header: Header {
+                seq: 0,
+                size: 0,
+                flags: FLAGS_PROBE,
+                name: None,
+                length: None,
+                policy: POLICY_NONE,
+                control: None,
+            },
+            payload: Vec::new(),
+            crc: None,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Builds a block that makes the receiver run dot command line `command`
+    /// once the connection is closed, see `PACKET_RUN`.
+    pub fn run(command: &[u8]) -> Block {
+        Block {
+            header: Header {
+                seq: 0,
+                size: command.len() as u16,
+                flags: FLAGS_LONG_HEADER,
+                name: None,
+                length: None,
+                policy: POLICY_NONE,
+                control: Some(PACKET_RUN),
+            },
+            payload: command.to_vec(),
+            crc: None,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Builds a block that ends the file of `size` bytes, see `PACKET_END`.
+    pub fn end(size: u32) -> Block {
+        Block {
+            header: Header {
+                seq: 0,
+                size: RESUME_PAYLOAD_LEN as u16,
+                flags: FLAGS_LONG_HEADER,
+                name: None,
+                length: None,
+                policy: POLICY_NONE,
+                control: Some(PACKET_END),
+            },
+            payload: size.to_le_bytes().to_vec(),
+            crc: None,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Builds a block that makes the receiver continue writing file `name`
+    /// at `offset`. `crc` is the CRC of the file data before `offset`, if the
+    /// blocks carry CRC.
+    pub fn resume(name: &[u8], offset: u32, crc: Option<u16>) -> Block {
+        let mut flags = FLAGS_LONG_HEADER | FLAGS_RESUME;
+        if crc.is_some() {
+            flags |= FLAGS_CRC;
+        }
+        Block {
+            header: Header {
+                seq: 0,
+                size: RESUME_PAYLOAD_LEN as u16,
+                flags,
+                name: Some(name.to_vec()),
+                length: None,
+                policy: POLICY_NONE,
+                control: None,
+            },
+            payload: offset.to_le_bytes().to_vec(),
+            crc,
+            raw_len: 0,
+            compressed_with: Compression::None,
+        }
+    }
+
+    /// Moves the name from the long header to the end of the payload, so it
+    /// can be longer than a DOS 8.3 one, see `FLAGS_LONG_NAME`.
+    pub fn with_long_name(mut self) -> Block {
+        if let Some(name) = self.header.name.take() {
+            if self.header.flags & (FLAGS_DIRECTORY | FLAGS_RESUME) == 0 {
+                self.payload.push(self.header.policy);
+                self.header.policy = POLICY_NONE;
+            }
+            self.payload.extend_from_slice(&name);
+            self.header.size = self.payload.len() as u16;
+            self.header.flags = self.header.flags & !FLAGS_LONG_HEADER | FLAGS_LONG_NAME;
+        }
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.wire_len());
+        self.header.encode(&mut out);
+        out.extend_from_slice(&self.payload);
+        if let Some(crc) = self.crc {
+            out.extend_from_slice(&crc.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Splits a file into blocks: numbers them, compresses them and puts the
+/// file name into the first one.
+#[derive(Debug)]
+pub struct BlockBuilder {
+    seq: u8,
+    name: Option<Vec<u8>>,
+    /// Collision policy sent along with the name
+    policy: u8,
+    compression: Compression,
+    /// CRC of the file data so far, if the blocks carry CRC
+    crc: Option<u16>,
+}
+
+impl BlockBuilder {
+    pub fn new(name: Vec<u8>, compression: Compression) -> BlockBuilder {
+        BlockBuilder {
+            seq: 0,
+            name: Some(name),
+            policy: POLICY_NONE,
+            compression,
+            crc: None,
+        }
+    }
+
+    /// Continues splitting a file at block `index`, the file name is expected
+    /// to be sent in a resume block beforehand.
+    pub fn resume(index: usize, compression: Compression) -> BlockBuilder {
+        BlockBuilder {
+            seq: index as u8,
+            name: None,
+            policy: POLICY_NONE,
+            compression,
+            crc: None,
+        }
+    }
+
+    /// Makes the blocks carry CRC, starting with `crc`: `CRC_INIT` for a new
+    /// file or the CRC of the data sent before for a resumed one.
+    pub fn with_crc(mut self, crc: u16) -> BlockBuilder {
+        self.crc = Some(crc);
+        self
+    }
+
+    /// Makes the receiver apply `collision` if the file exists.
+    pub fn with_collision(mut self, collision: Collision) -> BlockBuilder {
+        self.policy = collision.code();
+        self
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Builds the next block out of `chunk`, which must not be longer than
+    /// `CHUNK_SIZE`.
+    pub fn build(&mut self, chunk: &[u8]) -> Block {
+        let compressed = compress_chunk(self.compression, chunk);
+        self.build_compressed(chunk, compressed)
+    }
+
+    /// Builds the next block out of `chunk` and the result of
+    /// `compress_chunk` for it, which may be computed elsewhere.
+    pub fn build_compressed(&mut self, chunk: &[u8], compressed: Option<(Vec<u8>, Compression)>) -> Block {
+        assert!(chunk.len() <= CHUNK_SIZE);
+
+        let mut flags: u8 = 0;
+        let mut length = None;
+        if compressed.is_some() {
+            flags |= FLAGS_COMPRESSED;
+            if chunk.len() < CHUNK_SIZE {
+                flags |= FLAGS_LENGTH;
+                length = Some(chunk.len() as u16);
+            }
+        }
+        let (payload, compressed_with) = compressed.unwrap_or_else(|| (Vec::from(chunk), Compression::None));
+
+        let name = self.name.take();
+        let mut policy = POLICY_NONE;
+        if name.is_some() {
+            flags |= FLAGS_LONG_HEADER;
+            policy = self.policy;
+        }
+
+        if let Some(crc) = &mut self.crc {
+            *crc = crc16(*crc, chunk);
+            flags |= FLAGS_CRC;
+        }
+
+        let header = Header {
+            seq: self.seq,
+            size: payload.len() as u16,
+            flags,
+            name,
+            length,
+            policy,
+            control: None,
+        };
+        self.seq = self.seq.wrapping_add(1);
+
+        Block {
+            header,
+            payload,
+            crc: self.crc,
+            raw_len: chunk.len(),
+            compressed_with,
+        }
+    }
+}
+
+/// Compresses `chunk` with `compression`, returns the payload and the mode
+/// that produced it, or `None` if it's not worth it.
+pub fn compress_chunk(compression: Compression, chunk: &[u8]) -> Option<(Vec<u8>, Compression)> {
+    compression.compress(chunk).filter(|(payload, _)| pays_off(chunk.len(), payload.len()))
+}
+
+/// Checks whether a chunk of `chunk_len` bytes is worth sending as a
+/// compressed payload of `payload_len` bytes. A compressed partial block
+/// needs its length sent along, which must be paid off by the compression.
+pub fn pays_off(chunk_len: usize, payload_len: usize) -> bool {
+    chunk_len == CHUNK_SIZE || payload_len + LENGTH_LEN < chunk_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::CRC_INIT;
+
+    #[test]
+    fn name_goes_into_the_first_block() {
+        let mut builder = BlockBuilder::new(b"DATA.BIN".to_vec(), Compression::None);
+        let first = builder.build(&[1; CHUNK_SIZE]);
+        let second = builder.build(&[2; 10]);
+        assert_eq!(first.header.name.as_deref(), Some(&b"DATA.BIN"[..]));
+        assert_eq!(first.wire_len(), LONG_HEADER_LEN + CHUNK_SIZE);
+        assert_eq!((second.seq(), second.header.flags), (1, 0));
+        assert_eq!(second.wire_len(), SHORT_HEADER_LEN + 10);
+        assert_eq!(Header::parse(&second.to_bytes()), Some(second.header));
+    }
+
+    #[test]
+    fn compressed_partial_block() {
+        let chunk = [0x55; 500];
+        let block = BlockBuilder::resume(5, Compression::Optimal).with_crc(CRC_INIT).build(&chunk);
+        assert!(block.is_compressed());
+        assert_eq!(block.seq(), 5);
+        assert_eq!(block.header.flags, FLAGS_COMPRESSED | FLAGS_LENGTH | FLAGS_CRC);
+        assert_eq!(block.header.length, Some(500));
+        assert_eq!(block.crc, Some(crc16(CRC_INIT, &chunk)));
+
+        let bytes = block.to_bytes();
+        assert_eq!(bytes.len(), block.wire_len());
+        assert_eq!(Header::parse(&bytes), Some(block.header));
+        assert_eq!(&bytes[bytes.len() - CRC_LEN..], block.crc.unwrap().to_le_bytes());
+    }
+
+    #[test]
+    fn incomplete_header() {
+        let bytes = BlockBuilder::new(b"GAME.TAP".to_vec(), Compression::None).build(&[0; 10]).to_bytes();
+        assert_eq!(Header::peek_packet_len(&bytes[..SHORT_HEADER_LEN]), Some(bytes.len()));
+        assert_eq!(Header::peek_packet_len(&bytes[..SHORT_HEADER_LEN - 1]), None);
+        assert_eq!(Header::parse(&bytes[..LONG_HEADER_LEN - 1]), None);
+    }
+
+    #[test]
+    fn long_name() {
+        let block = Block::open(b"a long name.txt", Some(Collision::Fail)).with_long_name();
+        assert_eq!(block.header.flags, FLAGS_LONG_NAME);
+        assert_eq!(block.payload[0], Collision::Fail.code());
+        assert_eq!(&block.payload[1..], b"a long name.txt");
+        assert_eq!(block.wire_len(), SHORT_HEADER_LEN + 16);
+
+        let block = Block::resume(b"a long name.txt", 2048, None).with_long_name();
+        assert_eq!(block.header.flags, FLAGS_RESUME | FLAGS_LONG_NAME);
+        assert_eq!(&block.payload[..RESUME_PAYLOAD_LEN], 2048u32.to_le_bytes());
+        assert_eq!(&block.payload[RESUME_PAYLOAD_LEN..], b"a long name.txt");
+    }
+
+    #[test]
+    fn collision_policy() {
+        let block = Block::open(b"GAME.TAP", Some(Collision::Rename));
+        let bytes = block.to_bytes();
+        assert_eq!(bytes.len(), LONG_HEADER_LEN);
+        assert_eq!(&bytes[..4], [0, 0, 0, FLAGS_LONG_HEADER]);
+        assert_eq!(&bytes[4..16], b"GAME.TAP\0\0\0\0");
+        assert_eq!(bytes[16], Collision::Rename.code());
+        assert_eq!(Header::parse(&bytes), Some(block.header));
+    }
+
+    #[test]
+    fn compression_pays_off() {
+        assert!(pays_off(CHUNK_SIZE, CHUNK_SIZE - 1));
+        assert!(pays_off(100, 100 - LENGTH_LEN - 1));
+        assert!(!pays_off(100, 100 - LENGTH_LEN));
+    }
+
+    #[test]
+    fn control_packets() {
+        let bytes = Block::end(70000).to_bytes();
+        assert_eq!(bytes.len(), LONG_HEADER_LEN + RESUME_PAYLOAD_LEN);
+        assert_eq!(&bytes[LONG_HEADER_LEN..], 70000u32.to_le_bytes());
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!((header.name, header.control), (None, Some(PACKET_END)));
+
+        let bytes = Block::run(b"snapload GAME.SNA").to_bytes();
+        assert_eq!(&bytes[LONG_HEADER_LEN..], b"snapload GAME.SNA");
+        assert_eq!(Header::parse(&bytes).unwrap().control, Some(PACKET_RUN));
+
+        let header = Header::parse(&Block::open(b"A", None).to_bytes()).unwrap();
+        assert_eq!((header.name, header.control), (Some(b"A".to_vec()), None));
+    }
+}
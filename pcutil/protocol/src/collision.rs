@@ -0,0 +1,98 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::name::tilde_name;
+
+/// Policy byte of clients that don't choose one: the receiver overwrites the
+/// file and reports nothing.
+pub const POLICY_NONE: u8 = 0;
+pub const POLICY_OVERWRITE: u8 = 1;
+pub const POLICY_FAIL: u8 = 2;
+pub const POLICY_RENAME: u8 = 3;
+
+/// What the receiver does if the file it's told to create exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collision {
+    /// Replaces the existing file.
+    #[default]
+    Overwrite,
+    /// Gives up with `ERROR_EXISTS`.
+    Fail,
+    /// Saves the file as `NAME~1`, or up to `NAME~9` if that exists too.
+    Rename,
+}
+
+impl Collision {
+    pub const ALL: [Collision; 3] = [Collision::Overwrite, Collision::Fail, Collision::Rename];
+
+    /// Policy byte on the wire.
+    pub fn code(&self) -> u8 {
+        match self {
+            Collision::Overwrite => POLICY_OVERWRITE,
+            Collision::Fail => POLICY_FAIL,
+            Collision::Rename => POLICY_RENAME,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Collision> {
+        match code {
+            POLICY_OVERWRITE => Some(Collision::Overwrite),
+            POLICY_FAIL => Some(Collision::Fail),
+            POLICY_RENAME => Some(Collision::Rename),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Collision::Overwrite => "overwrite",
+            Collision::Fail => "fail",
+            Collision::Rename => "rename",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Collision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Collision, String> {
+        Collision::ALL.into_iter()
+            .find(|collision| collision.to_string() == s)
+            .ok_or_else(|| format!("{:?} is not one of overwrite, fail or rename", s))
+    }
+}
+
+/// What the receiver did to create a file, reported in the ACK if the
+/// client chose a collision policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// There was no such file.
+    Created,
+    /// The existing file was replaced.
+    Overwritten,
+    /// The file was saved as `NAME~N`.
+    Renamed(u8),
+}
+
+impl Decision {
+    /// Name the file was saved under, `name` is the one it was sent as.
+    pub fn saved_as(&self, name: &str, long_names: bool) -> String {
+        match self {
+            Decision::Renamed(n) => tilde_name(name, *n, long_names),
+            _ => name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Created => write!(f, "created"),
+            Decision::Overwritten => write!(f, "overwritten"),
+            Decision::Renamed(_) => write!(f, "renamed"),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::str::FromStr;
+use zx0::Compressor;
+
+/// ZX0 compression effort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Blocks are sent as they are.
+    None,
+    /// Fast, but doesn't compress as well as optimal.
+    #[default]
+    Quick,
+    /// Best ratio ZX0 can do, much slower.
+    Optimal,
+    /// Tries both quick and optimal and picks the smaller result for every
+    /// block.
+    Auto,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 4] = [Compression::None, Compression::Quick, Compression::Optimal, Compression::Auto];
+
+    /// Compresses `data`, returns the result along with the mode that
+    /// produced it, or `None` if it doesn't get any smaller.
+    pub fn compress(&self, data: &[u8]) -> Option<(Vec<u8>, Compression)> {
+        let quick = || (Compressor::new().quick_mode(true).compress(data).output, Compression::Quick);
+        let optimal = || (Compressor::new().compress(data).output, Compression::Optimal);
+
+        let candidates = match self {
+            Compression::None => return None,
+            Compression::Quick => vec![quick()],
+            Compression::Optimal => vec![optimal()],
+            Compression::Auto => vec![quick(), optimal()],
+        };
+
+        // Quick wins a tie as it comes first
+        candidates.into_iter()
+            .filter(|(output, _)| output.len() < data.len())
+            .min_by_key(|(output, _)| output.len())
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Quick => "quick",
+            Compression::Optimal => "optimal",
+            Compression::Auto => "auto",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        Compression::ALL.into_iter()
+            .find(|compression| compression.to_string() == s)
+            .ok_or_else(|| format!("{:?} is not one of none, quick, optimal or auto", s))
+    }
+}
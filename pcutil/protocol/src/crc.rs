@@ -0,0 +1,34 @@
+/// Initial value of CRC-16/CCITT-FALSE.
+pub const CRC_INIT: u16 = 0xffff;
+/// Size of the CRC on the wire.
+pub const CRC_LEN: usize = 2;
+
+/// Continues CRC-16/CCITT-FALSE `crc` over `data`.
+///
+/// Uses the same table-less byte at a time algorithm as `crc16` in the dot
+/// command.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc = crc.rotate_left(8) ^ byte as u16;
+        crc ^= (crc & 0xff) >> 4;
+        crc ^= crc << 12;
+        crc ^= (crc & 0xff) << 5;
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(CRC_INIT, b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn continues_over_parts() {
+        let (head, tail) = b"123456789".split_at(4);
+        assert_eq!(crc16(crc16(CRC_INIT, head), tail), 0x29b1);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use crate::block::PARENT_DIR;
+use crate::name::{filename_to_long, is_short_name};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    /// Drive isn't a single letter from A to P.
+    Drive(String),
+    /// Name isn't a valid DOS 8.3 one.
+    ShortName(String),
+    /// Name contains characters FAT doesn't allow or is too long.
+    LongName(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Drive(drive) => write!(f, "drive {:?} is not a letter from A to P", drive),
+            PathError::ShortName(name) => write!(f, "{:?} is not a valid DOS 8.3 name", name),
+            PathError::LongName(name) => write!(f, "{:?} is not a valid long file name", name),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Checks that `name` can be used on the Spectrum as it is, returns it the
+/// way it's sent: upper case if it's a DOS 8.3 one.
+pub fn remote_name(name: &str, long_names: bool) -> Result<String, PathError> {
+    if long_names {
+        if name.is_empty() || filename_to_long(name) != name {
+            return Err(PathError::LongName(name.to_string()));
+        }
+        Ok(name.to_string())
+    } else {
+        if !is_short_name(name) {
+            return Err(PathError::ShortName(name.to_string()));
+        }
+        Ok(name.to_ascii_uppercase())
+    }
+}
+
+/// Directory on the Spectrum the files are written into, in
+/// `[D:][/]DIR/DIR` form, where D is the drive letter. Without the drive it's
+/// on the current drive, without the leading slash it's relative to the
+/// current directory. Directories that don't exist are created.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Destination {
+    pub drive: Option<char>,
+    /// Path starts at the root of the drive.
+    pub absolute: bool,
+    pub dirs: Vec<String>,
+}
+
+impl Destination {
+    /// Parses `path`, either slash separates the directories. Every one of
+    /// them must be a valid DOS 8.3 name, or a long one with `long_names`.
+    pub fn parse(path: &str, long_names: bool) -> Result<Destination, PathError> {
+        let path = path.trim();
+        let (drive, path) = match path.split_once(':') {
+            Some((drive, rest)) => {
+                let mut letters = drive.chars().map(|c| c.to_ascii_uppercase());
+                match (letters.next(), letters.next()) {
+                    (Some(letter @ 'A'..='P'), None) => (Some(letter), rest),
+                    _ => return Err(PathError::Drive(drive.to_string())),
+                }
+            }
+            None => (None, path),
+        };
+
+        let absolute = path.starts_with(['/', '\\']);
+        let dirs = path
+            .split(['/', '\\'])
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| if dir == PARENT_DIR { Ok(dir.to_string()) } else { remote_name(dir, long_names) })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Destination { drive, absolute, dirs })
+    }
+
+    /// Whether it's the current directory.
+    pub fn is_empty(&self) -> bool {
+        self.drive.is_none() && !self.absolute && self.dirs.is_empty()
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(drive) = self.drive {
+            write!(f, "{}:", drive)?;
+        }
+        if self.absolute {
+            write!(f, "/")?;
+        }
+        write!(f, "{}", self.dirs.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let destination = Destination::parse(" c:/games/New ", false).unwrap();
+        assert_eq!(destination, Destination {
+            drive: Some('C'),
+            absolute: true,
+            dirs: vec!["GAMES".to_string(), "NEW".to_string()],
+        });
+        assert_eq!(destination.to_string(), "C:/GAMES/NEW");
+
+        let destination = Destination::parse("..\\demos\\", false).unwrap();
+        assert_eq!((destination.drive, destination.absolute), (None, false));
+        assert_eq!(destination.dirs, ["..", "DEMOS"]);
+
+        let destination = Destination::parse("/My Games", true).unwrap();
+        assert_eq!(destination.to_string(), "/My Games");
+    }
+
+    #[test]
+    fn current_directory() {
+        assert!(Destination::parse("", false).unwrap().is_empty());
+        assert!(!Destination::parse("/", false).unwrap().is_empty());
+        assert!(!Destination::parse("a:", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Destination::parse("q:/games", false), Err(PathError::Drive("q".to_string())));
+        assert_eq!(Destination::parse("cd:", false), Err(PathError::Drive("cd".to_string())));
+        assert_eq!(Destination::parse("games/long name", false),
+            Err(PathError::ShortName("long name".to_string())));
+        assert_eq!(Destination::parse("a?b", true), Err(PathError::LongName("a?b".to_string())));
+    }
+}
@@ -0,0 +1,101 @@
+use if_addrs::IfAddr;
+use log::info;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::ack::{ACK_LEN, Ack};
+use crate::block::Block;
+
+/// Time a single host is given to accept the connection and answer.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Larger subnets are only scanned around the local address.
+const MIN_PREFIX_LEN: u8 = 24;
+/// Hosts probed at once.
+const MAX_PROBES: usize = 256;
+
+/// Addresses of all the hosts on the local IPv4 subnets. Subnets larger than
+/// /24 would take ages to scan, so only the /24 around the local address is
+/// taken from them.
+pub fn local_hosts() -> io::Result<Vec<Ipv4Addr>> {
+    let mut hosts = Vec::new();
+    for interface in if_addrs::get_if_addrs()? {
+        let IfAddr::V4(addr) = interface.addr else {
+            continue;
+        };
+        if addr.ip.is_loopback() || addr.ip.is_link_local() {
+            continue;
+        }
+        let prefix_len = addr.prefixlen.max(MIN_PREFIX_LEN);
+        // Point to point links have no other hosts
+        if prefix_len >= 31 {
+            continue;
+        }
+        let mask = u32::MAX << (32 - prefix_len);
+        let network = u32::from(addr.ip) & mask;
+        let broadcast = network | !mask;
+        hosts.extend((network + 1..broadcast).map(Ipv4Addr::from));
+    }
+    hosts.sort();
+    hosts.dedup();
+    Ok(hosts)
+}
+
+/// Checks whether the dot command listens at `addr`: sends it a probe block
+/// and waits for the ACK, `limit` is the time it's given for everything.
+/// Dot commands older than v0.5 quit after it.
+pub async fn probe(addr: SocketAddr, limit: Duration) -> bool {
+    let block = Block::probe();
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&block.to_bytes()).await?;
+        let mut read_buf = [0u8; ACK_LEN];
+        stream.read_exact(&mut read_buf).await?;
+        Ok::<_, io::Error>(Ack::decode(&read_buf))
+    };
+
+    match tokio::time::timeout(limit, exchange).await {
+        Ok(Ok(ack)) if ack.seq == block.seq() && ack.size as usize == block.wire_len() => match ack.failure() {
+            None => true,
+            Some(error) => {
+                // Versions without probes take it for a data block and quit
+                info!("{} answered with \"{}\", looks like an older zxmit", addr, error);
+                false
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Probes all the hosts on the local subnets on `port`, returns the ones
+/// the dot command listens at. It ends all the dot commands older than v0.5
+/// around, so it's only meant to be run once the user confirmed it.
+pub async fn discover(port: u16, limit: Duration) -> io::Result<Vec<SocketAddr>> {
+    let hosts = local_hosts()?;
+    info!("Probing {} hosts on port {}", hosts.len(), port);
+
+    let permits = Arc::new(Semaphore::new(MAX_PROBES));
+    let mut probes = JoinSet::new();
+    for host in hosts {
+        let addr = SocketAddr::new(IpAddr::V4(host), port);
+        let permits = permits.clone();
+        probes.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            probe(addr, limit).await.then_some(addr)
+        });
+    }
+
+    let mut found = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        if let Ok(Some(addr)) = result {
+            found.push(addr);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
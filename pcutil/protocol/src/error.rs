@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::ack::AckError;
+use crate::address::Address;
+
+/// Failure reported by the receiver, along with the block that caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiverError {
+    pub error: AckError,
+    /// Index of the block in the file, `None` if the sequence in the ACK
+    /// doesn't match any of the blocks sent.
+    pub block: Option<usize>,
+    pub seq: u8,
+    /// Offset of the block data in the file.
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for ReceiverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiver error: {}", self.error)?;
+        match (self.block, self.offset) {
+            (Some(block), Some(offset)) => write!(f, " at block {} (seq {}, offset {})", block, self.seq, offset),
+            _ => write!(f, " (seq {})", self.seq),
+        }
+    }
+}
+
+/// What was going on when the receiver stopped responding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stall {
+    Write,
+    Ack,
+}
+
+/// Receiver stopped responding in the middle of the transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError {
+    pub stall: Stall,
+    pub after: Duration,
+    /// Index of the block in the file.
+    pub block: usize,
+    pub seq: u8,
+    /// Offset of the block data in the file.
+    pub offset: usize,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.stall {
+            Stall::Write => "sending",
+            Stall::Ack => "waiting for ACK of",
+        };
+        write!(f, "timed out after {:?} {} block {} (seq {}, offset {})",
+            self.after, action, self.block, self.seq, self.offset)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// File name doesn't fit into the header, along with the longest one
+    /// that does.
+    Name(String, usize),
+    /// Host name couldn't be resolved.
+    Resolve(Address, io::Error),
+    /// None of the addresses the host resolved to accepted the connection.
+    Connect(Address, Vec<(SocketAddr, io::Error)>),
+    Receiver(ReceiverError),
+    Timeout(TimeoutError),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Name(name, max_len) => write!(f, "file name {:?} is longer than {} characters", name, max_len),
+            Error::Resolve(address, e) => write!(f, "failed to resolve {}: {}", address.host, e),
+            Error::Connect(address, attempts) => {
+                write!(f, "failed to connect to {}", address)?;
+                if attempts.is_empty() {
+                    return write!(f, ": host has no addresses");
+                }
+                write!(f, ", tried:")?;
+                for (addr, e) in attempts {
+                    write!(f, "\n  {}: {}", addr, e)?;
+                }
+                Ok(())
+            }
+            Error::Receiver(e) => e.fmt(f),
+            Error::Timeout(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Resolve(_, e) | Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
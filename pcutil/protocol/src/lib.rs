@@ -0,0 +1,81 @@
+//! Wire protocol spoken by the zxmit dot command and a client to drive it.
+//!
+//! It needs the dot command v0.5 or later, older ones reject the compressed
+//! last block of a file.
+//!
+//! # Blocks
+//!
+//! The Spectrum side listens on a TCP port and expects the file split into
+//! blocks of at most `CHUNK_SIZE` bytes, see [`block`]. The first one has a
+//! 17-byte long header with the DOS 8.3 file name, the rest a 4-byte short
+//! one. A long header closes the open file and starts the next one, so any
+//! number of files go over a single connection. Directory and control
+//! packets use the long header too. Every file is ended by a control packet
+//! the receiver confirms once the file is written out in full.
+//!
+//! # Acknowledgements
+//!
+//! Every block is acknowledged with a 4-byte ACK, see [`ack`], which also
+//! carries the errors and what was done with an existing file. A window of
+//! blocks can be kept in flight, and a receiver that doesn't acknowledge a
+//! block in time is considered gone, see [`net::Timeouts`].
+//!
+//! # Sending
+//!
+//! [`Uploader`] compresses the blocks in parallel ahead of sending them, see
+//! [`pipeline`], paces them if asked to, see [`pacing`], and resumes a file at
+//! the last acknowledged block over a new connection. Blocks may carry a
+//! running CRC of the file data for the receiver to verify. The data may come
+//! from any async reader, and [`watch`] sends files again when they change.
+//!
+//! # Extras
+//!
+//! - [`discover()`] finds receivers on the local network by probing it.
+//! - [`destination`] and [`collision`] tell where the files go and what to
+//!   do if one exists, [`name`] converts names for the target.
+//! - [`run`] makes the receiver run the file it got.
+//! - [`analysis`] shows offline how a file would be sent and how long it
+//!   takes.
+
+pub mod ack;
+pub mod address;
+pub mod analysis;
+pub mod block;
+pub mod collision;
+pub mod compression;
+pub mod crc;
+pub mod destination;
+pub mod discover;
+pub mod error;
+pub mod name;
+pub mod net;
+pub mod pacing;
+pub mod pipeline;
+pub mod run;
+pub mod upload;
+pub mod watch;
+
+pub use ack::{ACK_LEN, Ack, AckError};
+pub use address::{Address, AddressError};
+pub use analysis::{BlockReport, Driver, RawReason, Report};
+pub use block::{Block, BlockBuilder, Header, CHUNK_SIZE, FLAGS_COMPRESSED, FLAGS_CRC, FLAGS_DIRECTORY,
+    FLAGS_LENGTH, FLAGS_LONG_HEADER, FLAGS_LONG_NAME, FLAGS_PROBE, FLAGS_RESUME, LENGTH_LEN, LONG_HEADER_LEN,
+    MAX_RUN_COMMAND_LEN, PACKET_END, PACKET_RUN, PARENT_DIR, RESUME_PAYLOAD_LEN, ROOT_DIR, SHORT_HEADER_LEN,
+    compress_chunk, pays_off};
+pub use collision::{Collision, Decision};
+pub use compression::Compression;
+pub use crc::{CRC_INIT, crc16};
+pub use destination::{Destination, PathError, remote_name};
+pub use discover::{DEFAULT_PROBE_TIMEOUT, discover, local_hosts, probe};
+pub use name::{MAX_LONG_NAME_LEN, dirname_to_short, filename_to_long, filename_to_short, is_short_name,
+    tilde_name};
+pub use error::{Error, ReceiverError, Stall, TimeoutError};
+pub use net::{DEFAULT_ACK_TIMEOUT, DEFAULT_CONNECT_TIMEOUT, DEFAULT_WRITE_TIMEOUT, Timeouts};
+pub use pacing::Pacing;
+pub use pipeline::Pipeline;
+pub use run::{RunError, only_attaches, run_command};
+pub use upload::{Connection, DEFAULT_WINDOW, MAX_WINDOW, Progress, Upload, UploadEvent, Uploader};
+pub use watch::{DEFAULT_DEBOUNCE, POLL_INTERVAL, Stamp, Watcher};
+
+/// TCP port the dot command listens on.
+pub const DEFAULT_PORT: u16 = 6144;
@@ -0,0 +1,114 @@
+use regex::Regex;
+
+/// Longest DOS 8.3 name, without the terminating zero.
+pub const MAX_SHORT_NAME_LEN: usize = 12;
+/// Longest name sent to the targets that support long file names.
+pub const MAX_LONG_NAME_LEN: usize = 128;
+/// Longest extension kept when a long name is truncated.
+const MAX_LONG_EXTENSION_LEN: usize = 16;
+
+fn split_at_last_dot(filename: &str) -> (String, String) {
+    let parts: Vec<&str> = filename.split('.').collect();
+
+    if parts.len() <= 1 {
+        return (filename.to_string(), "".to_string());
+    }
+
+    let last = parts.last().unwrap().to_string();
+    let first = parts[..parts.len() - 1].join(" ");
+
+    (first, last)
+}
+
+fn to_dos_chars(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+/// Converts a file name into an upper case DOS 8.3 name that esxDOS accepts.
+///
+/// Non-ASCII characters, spaces, dots and path separators are replaced
+/// with underscores, then the name and extension are truncated to 8 and 3
+/// characters respectively.
+pub fn filename_to_short(filename: &str) -> String {
+    let (name, extension) = split_at_last_dot(filename);
+
+    let name: String = to_dos_chars(&name).chars().take(8).collect();
+    let extension: String = to_dos_chars(&extension).chars().take(3).collect();
+
+    let re = Regex::new(r"[ \t\.\\/]").unwrap();
+
+    let name = re.replace_all(&name, "_");
+    let extension = re.replace_all(&extension, "_");
+
+    format!("{}.{}", name, extension)
+}
+
+/// Converts a directory name into a DOS 8.3 name, without the trailing dot
+/// if there's no extension.
+pub fn dirname_to_short(dirname: &str) -> String {
+    filename_to_short(dirname).trim_end_matches('.').to_string()
+}
+
+/// Converts a file or directory name into a long one that FAT accepts,
+/// keeping the case.
+///
+/// Non-ASCII and control characters and the ones FAT doesn't allow are
+/// replaced with underscores, trailing dots and spaces are dropped, then the
+/// name is truncated to `MAX_LONG_NAME_LEN` characters keeping the extension.
+pub fn filename_to_long(filename: &str) -> String {
+    let name: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && !"\"*/:<>?\\|".contains(c) { c } else { '_' })
+        .collect();
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return "_".to_string();
+    }
+    if name.len() <= MAX_LONG_NAME_LEN {
+        return name.to_string();
+    }
+
+    // All ASCII by now, so any byte index is a char boundary
+    let extension = match name.rfind('.') {
+        Some(dot) if name.len() - dot <= MAX_LONG_EXTENSION_LEN => &name[dot..],
+        _ => "",
+    };
+    format!("{}{}", &name[..MAX_LONG_NAME_LEN - extension.len()], extension)
+}
+
+/// Checks whether `name` is a valid DOS 8.3 name, in any case.
+pub fn is_short_name(name: &str) -> bool {
+    let (stem, extension) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max_len: usize| {
+        part.len() <= max_len && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c))
+    };
+    !stem.is_empty() && valid(stem, 8) && valid(extension, 3)
+}
+
+/// Alternative `n` to `name` for a file that exists: `NAME~N.EXT`. DOS 8.3
+/// names keep up to 6 characters before the extension, long names all of
+/// them.
+pub fn tilde_name(name: &str, n: u8, long_names: bool) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) => name.split_at(dot),
+        None => (name, ""),
+    };
+    let stem: String = if long_names { stem.to_string() } else { stem.chars().take(6).collect() };
+    format!("{}~{}{}", stem, n, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tilde_names() {
+        assert_eq!(tilde_name("GAMEDATA.TAP", 1, false), "GAMEDA~1.TAP");
+        assert_eq!(tilde_name("A.B", 2, false), "A~2.B");
+        assert_eq!(tilde_name("README", 9, false), "README~9");
+        assert_eq!(tilde_name("my long name.txt", 3, true), "my long name~3.txt");
+        assert_eq!(tilde_name("archive.tar.gz", 1, true), "archive.tar~1.gz");
+    }
+}
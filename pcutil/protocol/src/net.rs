@@ -0,0 +1,79 @@
+use log::info;
+use std::io;
+use std::time::Duration;
+use tokio::net::{TcpStream, lookup_host};
+
+use crate::address::Address;
+use crate::error::Error;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the receiver, `None` waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting to a single address.
+    pub connect: Option<Duration>,
+    /// Writing a single block into the socket.
+    pub write: Option<Duration>,
+    /// Waiting for the whole block to be acknowledged, however many ACKs it
+    /// takes. With a window it starts once the block before it is
+    /// acknowledged.
+    pub ack: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Makes timeouts out of a number of seconds, 0 means no timeout.
+    pub fn from_secs(connect: u64, write: u64, ack: u64) -> Timeouts {
+        let secs = |secs| if secs == 0 { None } else { Some(Duration::from_secs(secs)) };
+        Timeouts {
+            connect: secs(connect),
+            write: secs(write),
+            ack: secs(ack),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Some(DEFAULT_CONNECT_TIMEOUT),
+            write: Some(DEFAULT_WRITE_TIMEOUT),
+            ack: Some(DEFAULT_ACK_TIMEOUT),
+        }
+    }
+}
+
+/// Runs `future` to completion, returns `None` if it takes longer than
+/// `limit`.
+pub(crate) async fn timeout<F: Future>(limit: Option<Duration>, future: F) -> Option<F::Output> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Connects to the receiver, trying every address the host resolves to in
+/// turn. Each attempt is given up after `limit`.
+pub async fn connect(address: &Address, limit: Option<Duration>) -> Result<TcpStream, Error> {
+    let addrs = lookup_host((address.host.as_str(), address.port))
+        .await
+        .map_err(|e| Error::Resolve(address.clone(), e))?;
+
+    let mut attempts = Vec::new();
+    for addr in addrs {
+        info!("Connecting to {}", addr);
+        match timeout(limit, TcpStream::connect(addr)).await {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => attempts.push((addr, e)),
+            None => {
+                let e = io::Error::new(io::ErrorKind::TimedOut,
+                    format!("timed out after {:?}", limit.unwrap_or_default()));
+                attempts.push((addr, e));
+            }
+        }
+    }
+
+    Err(Error::Connect(address.clone(), attempts))
+}
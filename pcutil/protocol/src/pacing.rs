@@ -0,0 +1,98 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How fast the blocks are written, for receivers or networks that can't
+/// keep up. Doesn't limit anything by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pacing {
+    /// Most bytes per second written into the socket, headers included,
+    /// `None` doesn't limit it.
+    pub rate: Option<u32>,
+    /// Pause after every block on top of the rate.
+    pub delay: Duration,
+}
+
+impl Pacing {
+    /// Makes pacing out of a rate and a delay in milliseconds, 0 turns
+    /// either of them off.
+    pub fn from_rate_and_millis(rate: u32, delay: u64) -> Pacing {
+        Pacing {
+            rate: if rate == 0 { None } else { Some(rate) },
+            delay: Duration::from_millis(delay),
+        }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.rate.is_some() || !self.delay.is_zero()
+    }
+
+    /// Shortest time it lets `wire_bytes` through in `blocks` blocks.
+    pub fn min_time(&self, wire_bytes: usize, blocks: usize) -> Duration {
+        let transfer = self.rate.map_or(Duration::ZERO, |rate| {
+            Duration::from_secs_f64(wire_bytes as f64 / rate as f64)
+        });
+        transfer + self.delay * blocks as u32
+    }
+}
+
+/// Holds back the writes to keep to `Pacing`.
+#[derive(Debug, Clone)]
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    /// When the next block may be written
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    pub(crate) fn new(pacing: Pacing) -> Pacer {
+        Pacer { pacing, next: None }
+    }
+
+    /// Waits until a block of `len` bytes may be written, the writes after
+    /// it wait for the time it takes at the rate plus the delay.
+    pub(crate) async fn wait(&mut self, len: usize) {
+        if !self.pacing.is_limited() {
+            return;
+        }
+        if let Some(next) = self.next {
+            tokio::time::sleep_until(next).await;
+        }
+        self.next = Some(Instant::now() + self.pacing.min_time(len, 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_time() {
+        let pacing = Pacing::from_rate_and_millis(1024, 10);
+        assert!(pacing.is_limited());
+        assert_eq!(pacing.min_time(512, 2), Duration::from_millis(520));
+        assert_eq!(Pacing::from_rate_and_millis(0, 0), Pacing::default());
+        assert_eq!(Pacing::default().min_time(1 << 20, 100), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pacer_holds_back_the_next_block() {
+        let mut pacer = Pacer::new(Pacing::from_rate_and_millis(1024, 100));
+        let start = Instant::now();
+        pacer.wait(512).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        pacer.wait(256).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(600));
+        pacer.wait(0).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(950));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_pacer_never_waits() {
+        let mut pacer = Pacer::new(Pacing::default());
+        let start = Instant::now();
+        for _ in 0..10 {
+            pacer.wait(1024).await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
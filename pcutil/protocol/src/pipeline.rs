@@ -0,0 +1,80 @@
+use std::io;
+use std::thread;
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+
+use crate::block::{Block, BlockBuilder, CHUNK_SIZE, compress_chunk};
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::upload::Progress;
+
+/// Chunk of the file along with its compressed payload, if any.
+type Compressed = (Vec<u8>, Option<(Vec<u8>, Compression)>);
+
+/// Blocks of a file being prepared for sending.
+///
+/// Chunks are compressed in parallel on the blocking thread pool, at most
+/// `look_ahead()` of them ahead of the block being sent, and come out in
+/// the file order. Compression starts as soon as the pipeline is created,
+/// so it can be done before connecting to the receiver.
+#[derive(Debug)]
+pub struct Pipeline {
+    rx: mpsc::Receiver<JoinHandle<Compressed>>,
+    builder: BlockBuilder,
+    pub(crate) progress: Progress,
+    /// Block that opens the file ahead of the data, if the name doesn't go
+    /// into the first block
+    pub(crate) opening: Option<Block>,
+}
+
+impl Pipeline {
+    /// Starts compressing `buffer`, the blocks are numbered by `builder` and
+    /// `progress` is what was sent before them. Panics outside of a Tokio
+    /// runtime, the compression is spawned on it.
+    pub(crate) fn start(buffer: Vec<u8>, builder: BlockBuilder, progress: Progress) -> Pipeline {
+        let compression = builder.compression();
+        let (tx, rx) = mpsc::channel(look_ahead());
+        tokio::spawn(async move {
+            for chunk in buffer.chunks(CHUNK_SIZE) {
+                let chunk = chunk.to_vec();
+                let handle = task::spawn_blocking(move || {
+                    let compressed = compress_chunk(compression, &chunk);
+                    (chunk, compressed)
+                });
+                // Waits here while the look-ahead is used up
+                if tx.send(handle).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Pipeline {
+            rx,
+            builder,
+            progress,
+            opening: None,
+        }
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Waits until the next block is compressed.
+    ///
+    /// Returns `None` once all the blocks were taken, fails if compressing
+    /// the block panicked.
+    pub async fn next(&mut self) -> Result<Option<Block>, Error> {
+        let Some(handle) = self.rx.recv().await else {
+            return Ok(None);
+        };
+        let (chunk, compressed) = handle.await.map_err(io::Error::from)?;
+        Ok(Some(self.builder.build_compressed(&chunk, compressed)))
+    }
+}
+
+/// Number of blocks compressed ahead of the one being sent, enough to keep
+/// every core busy.
+fn look_ahead() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get() * 2)
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::block::MAX_RUN_COMMAND_LEN;
+
+/// Dot commands that run files, by the extensions of the files, and
+/// whether they start the file or only attach it.
+const RUNNERS: [(&str, &[&str], bool); 3] = [
+    ("snapload", &["SNA", "Z80", "SNX"], true),
+    ("tapein", &["TAP"], false),
+    ("nexload", &["NEX"], true),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// There's no dot command known to run files with the extension.
+    Extension(String),
+    /// Command line is too long or isn't printable ASCII.
+    Command(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Extension(name) => write!(f, "don't know how to run {:?}, give the command to run it", name),
+            RunError::Command(command) => write!(f, "{:?} is not a command line of up to {} ASCII characters",
+                command, MAX_RUN_COMMAND_LEN),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// Dot command line, without the dot, that makes the Spectrum run file
+/// `name` as it was saved: snapshots are loaded, tapes are attached for
+/// LOAD "" and NEX files are loaded on the Next. A file without an extension
+/// is taken for a dot command. `command` is checked and used instead if
+/// it's given, with or without the dot.
+pub fn run_command(name: &str, command: Option<&str>) -> Result<String, RunError> {
+    let command = match command {
+        Some(command) => command.trim().trim_start_matches('.').to_string(),
+        None => match name.rsplit_once('.') {
+            None => name.to_string(),
+            Some((_, extension)) => {
+                let extension = extension.to_ascii_uppercase();
+                let (runner, _, _) = RUNNERS.iter()
+                    .find(|(_, extensions, _)| extensions.contains(&extension.as_str()))
+                    .ok_or_else(|| RunError::Extension(name.to_string()))?;
+                // Long names may have spaces
+                if name.contains(' ') {
+                    format!("{} \"{}\"", runner, name)
+                } else {
+                    format!("{} {}", runner, name)
+                }
+            }
+        },
+    };
+    let printable = command.chars().all(|c| c.is_ascii_graphic() || c == ' ');
+    if command.is_empty() || command.len() > MAX_RUN_COMMAND_LEN || !printable {
+        return Err(RunError::Command(command));
+    }
+    Ok(command)
+}
+
+/// Whether `command` only attaches the file instead of starting it, like
+/// `.tapein` does with tapes, which still have to be loaded with LOAD "".
+pub fn only_attaches(command: &str) -> bool {
+    let runner = command.split(' ').next().unwrap_or_default();
+    RUNNERS.iter().any(|(name, _, starts)| !starts && name.eq_ignore_ascii_case(runner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_extension() {
+        assert_eq!(run_command("GAME.SNA", None), Ok("snapload GAME.SNA".to_string()));
+        assert_eq!(run_command("game.tap", None), Ok("tapein game.tap".to_string()));
+        assert_eq!(run_command("my game.nex", None), Ok("nexload \"my game.nex\"".to_string()));
+        assert_eq!(run_command("TOOL", None), Ok("TOOL".to_string()));
+        assert_eq!(run_command("FILE.XYZ", None), Err(RunError::Extension("FILE.XYZ".to_string())));
+    }
+
+    #[test]
+    fn given_command() {
+        assert_eq!(run_command("GAME.SNA", Some(" .nexload X.NEX ")), Ok("nexload X.NEX".to_string()));
+        assert!(run_command("GAME.SNA", Some(&"a".repeat(MAX_RUN_COMMAND_LEN))).is_ok());
+        assert!(run_command("GAME.SNA", Some(&"a".repeat(MAX_RUN_COMMAND_LEN + 1))).is_err());
+        assert_eq!(run_command("GAME.SNA", Some(".")), Err(RunError::Command(String::new())));
+        assert!(run_command("GAME.SNA", Some("ls\x7f")).is_err());
+    }
+
+    #[test]
+    fn attaching() {
+        assert!(only_attaches("tapein GAME.TAP"));
+        assert!(only_attaches("TAPEIN GAME.TAP"));
+        assert!(!only_attaches("snapload GAME.SNA"));
+        assert!(!only_attaches("tapeinfo"));
+    }
+}
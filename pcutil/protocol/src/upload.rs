@@ -0,0 +1,594 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::ack::{ACK_LEN, Ack, AckError};
+use crate::address::Address;
+use crate::block::{Block, BlockBuilder, CHUNK_SIZE, PARENT_DIR, ROOT_DIR};
+use crate::collision::{Collision, Decision};
+use crate::compression::Compression;
+use crate::crc::{CRC_INIT, crc16};
+use crate::destination::Destination;
+use crate::error::{Error, ReceiverError, Stall, TimeoutError};
+use crate::name::{MAX_LONG_NAME_LEN, MAX_SHORT_NAME_LEN, dirname_to_short, filename_to_long};
+use crate::net::{Timeouts, connect, timeout};
+use crate::pacing::{Pacer, Pacing};
+use crate::pipeline::Pipeline;
+
+/// Blocks in flight by default, every block waits for the ACK of the
+/// previous one.
+pub const DEFAULT_WINDOW: usize = 1;
+/// Most blocks in flight, half of the sequence numbers, so a sequence in an
+/// ACK always tells the block apart.
+pub const MAX_WINDOW: usize = 128;
+/// Bytes per second the receiver reads back and checks of a file being
+/// resumed with CRC, a Spectrum at 3.5MHz does a bit more.
+const RESUME_CHECK_RATE: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    /// Number of blocks sent and acknowledged so far.
+    pub current_block: usize,
+    pub blocks_num: usize,
+    pub total_bytes: usize,
+    /// File data sent and acknowledged so far, before compression.
+    pub sent_bytes: usize,
+    /// Bytes sent so far, including headers.
+    pub compressed_bytes: usize,
+    /// CRC of the file data acknowledged so far, the CRC of the whole file
+    /// once it's sent. Only if the blocks carry CRC.
+    pub crc: Option<u16>,
+    /// Bytes saved by the blocks compressed in quick mode.
+    pub quick_saved: usize,
+    /// Bytes saved by the blocks compressed in optimal mode.
+    pub optimal_saved: usize,
+    /// What the receiver did to create the file, if the client chose a
+    /// collision policy.
+    pub decision: Option<Decision>,
+    /// Receiver confirmed the whole file was written out and closed. Older
+    /// receivers don't.
+    pub committed: bool,
+}
+
+impl Progress {
+    /// Time left to send the rest of the file: at the speed so far, it took
+    /// `elapsed` to get here, but no sooner than `pacing` lets it through.
+    /// The rest is expected to compress like the data sent so far.
+    pub fn eta(&self, elapsed: Duration, pacing: &Pacing) -> Option<Duration> {
+        let left = self.total_bytes.saturating_sub(self.sent_bytes);
+        let measured = (self.sent_bytes > 0).then(|| elapsed.mul_f64(left as f64 / self.sent_bytes as f64));
+        if !pacing.is_limited() {
+            return measured;
+        }
+        let ratio = if self.sent_bytes > 0 { self.compressed_bytes as f64 / self.sent_bytes as f64 } else { 1.0 };
+        let paced = pacing.min_time((left as f64 * ratio) as usize, self.blocks_num.saturating_sub(self.current_block));
+        Some(measured.map_or(paced, |measured| measured.max(paced)))
+    }
+}
+
+/// Something that went over the wire during `Upload::next`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadEvent {
+    /// Block `index` of the file was sent. The block opening a file with a
+    /// long name goes ahead of the data, with the index 0 as well.
+    Sent {
+        index: usize,
+        seq: u8,
+        raw_len: usize,
+        wire_len: usize,
+        compressed_with: Compression,
+    },
+    /// Receiver acknowledged the data.
+    Ack(Ack),
+}
+
+/// Sends files to a ZX Spectrum running the dot command.
+#[derive(Debug, Clone)]
+pub struct Uploader {
+    pub address: Address,
+    pub compression: Compression,
+    /// Make the receiver verify CRC of every block, older versions of the dot
+    /// command reject such blocks.
+    pub use_crc: bool,
+    /// Prepare the blocks without any networking communication.
+    pub dummy: bool,
+    pub timeouts: Timeouts,
+    /// Number of blocks sent ahead without waiting for their ACKs, up to
+    /// `MAX_WINDOW`.
+    pub window: usize,
+    /// Send the names as they are, up to `MAX_LONG_NAME_LEN` bytes, instead
+    /// of DOS 8.3 ones. Only for targets that support long file names.
+    pub long_names: bool,
+    /// Directory the files are written into, the current one of the
+    /// receiver if it's empty.
+    pub destination: Destination,
+    /// What the receiver does if a file exists, `None` overwrites it without
+    /// reporting anything, like the older versions do.
+    pub collision: Option<Collision>,
+    /// How fast the blocks are written.
+    pub pacing: Pacing,
+}
+
+impl Uploader {
+    /// Connects to the receiver, unless it's a dummy run, and makes it enter
+    /// the destination.
+    pub async fn connect(&self) -> Result<Connection, Error> {
+        let stream = if self.dummy {
+            None
+        } else {
+            Some(connect(&self.address, self.timeouts.connect).await?)
+        };
+
+        let mut connection = Connection {
+            stream,
+            compression: self.compression,
+            use_crc: self.use_crc,
+            timeouts: self.timeouts,
+            window: self.window.clamp(1, MAX_WINDOW),
+            long_names: self.long_names,
+            collision: self.collision,
+            pacer: Pacer::new(self.pacing),
+        };
+        connection.enter(&self.destination).await?;
+        Ok(connection)
+    }
+
+    /// Starts compressing `buffer` for sending it as `name`, which must be
+    /// a DOS 8.3 name, unless `long_names` is set. Meant to be called before
+    /// `connect`, so the first block is ready by the time the connection is.
+    /// Must be called within a Tokio runtime, as the compression runs on it.
+    pub fn prepare(&self, name: &str, buffer: Vec<u8>) -> Result<Pipeline, Error> {
+        prepare(name, buffer, self.compression, self.use_crc, self.long_names, self.collision)
+    }
+
+    /// Reads `reader` to the end and starts compressing the data for sending
+    /// it as `name`, like `prepare`. For the data that doesn't come from a
+    /// file, e.g. the output of another program or the clipboard contents.
+    pub async fn prepare_from(&self, name: &str, reader: impl AsyncRead + Unpin) -> Result<Pipeline, Error> {
+        check_name(name, self.long_names)?;
+        self.prepare(name, read_all(reader).await?)
+    }
+}
+
+/// Connection to the receiver, any number of files can be sent over it one
+/// after another.
+#[derive(Debug)]
+pub struct Connection {
+    stream: Option<TcpStream>,
+    compression: Compression,
+    use_crc: bool,
+    timeouts: Timeouts,
+    window: usize,
+    long_names: bool,
+    collision: Option<Collision>,
+    pacer: Pacer,
+}
+
+impl Connection {
+    /// Makes the receiver create directory `name`, unless it exists, and
+    /// enter it. The name is converted into a DOS 8.3 one, or a long one if
+    /// `long_names` is set.
+    pub async fn enter_dir(&mut self, name: &str) -> Result<(), Error> {
+        let name = if self.long_names { filename_to_long(name) } else { dirname_to_short(name) };
+        self.send_dir(&name).await
+    }
+
+    /// Makes the receiver select the drive of `destination`, if any, and
+    /// enter its directories one by one, creating the ones that don't exist.
+    /// The names are expected to be checked by `Destination::parse`.
+    pub async fn enter(&mut self, destination: &Destination) -> Result<(), Error> {
+        if let Some(drive) = destination.drive {
+            self.send_dir(&format!("{}:", drive)).await?;
+        }
+        if destination.absolute {
+            self.send_dir(ROOT_DIR).await?;
+        }
+        for dir in &destination.dirs {
+            self.send_dir(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Makes the receiver go back to the parent directory.
+    pub async fn leave_dir(&mut self) -> Result<(), Error> {
+        self.send_dir(PARENT_DIR).await
+    }
+
+    async fn send_dir(&mut self, name: &str) -> Result<(), Error> {
+        if let Some(stream) = &mut self.stream {
+            let mut block = Block::directory(name.as_bytes());
+            if self.long_names {
+                block = block.with_long_name();
+            }
+            send_block(stream, &mut self.pacer, &block, 0, &self.timeouts).await?;
+        }
+        Ok(())
+    }
+
+    /// Makes the receiver run dot command line `command` once the
+    /// connection is closed, closing the file sent last. The command is
+    /// expected to be checked by `run_command`.
+    pub async fn run(&mut self, command: &str) -> Result<(), Error> {
+        if let Some(stream) = &mut self.stream {
+            send_block(stream, &mut self.pacer, &Block::run(command.as_bytes()), 0, &self.timeouts).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts preparing blocks of `buffer` for sending it as `name`, which
+    /// must be a DOS 8.3 name, unless `long_names` is set.
+    pub fn upload(&mut self, name: &str, buffer: Vec<u8>) -> Result<Upload<'_>, Error> {
+        let pipeline = prepare(name, buffer, self.compression, self.use_crc, self.long_names, self.collision)?;
+        Ok(self.send(pipeline))
+    }
+
+    /// Reads `reader` to the end and starts preparing the blocks of the data
+    /// for sending it as `name`, see `Uploader::prepare_from`.
+    pub async fn upload_from(&mut self, name: &str, reader: impl AsyncRead + Unpin) -> Result<Upload<'_>, Error> {
+        check_name(name, self.long_names)?;
+        let buffer = read_all(reader).await?;
+        self.upload(name, buffer)
+    }
+
+    /// Sends the blocks of `pipeline`, see `Uploader::prepare`.
+    pub fn send(&mut self, pipeline: Pipeline) -> Upload<'_> {
+        Upload {
+            stream: self.stream.as_mut(),
+            pacer: &mut self.pacer,
+            timeouts: self.timeouts,
+            window: self.window,
+            pipeline,
+            in_flight: VecDeque::new(),
+            acked: 0,
+            ended: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Continues an upload of `buffer` as `name` that was interrupted after
+    /// `progress`, normally over a new connection. The receiver reopens the
+    /// file, under the name it was renamed to if it was, and the blocks that
+    /// were acknowledged already are skipped.
+    ///
+    /// Starts over if nothing was acknowledged yet or the file changed size.
+    pub async fn resume(&mut self, name: &str, buffer: Vec<u8>, progress: Progress) -> Result<Upload<'_>, Error> {
+        if progress.current_block == 0 {
+            return self.upload(name, buffer);
+        }
+        if progress.total_bytes != buffer.len() {
+            warn!("{} changed size from {} to {} bytes, starting over", name, progress.total_bytes, buffer.len());
+            return self.upload(name, buffer);
+        }
+        let name = &progress.decision.map_or(name.to_string(), |decision| decision.saved_as(name, self.long_names));
+        check_name(name, self.long_names)?;
+
+        let offset = (progress.current_block * CHUNK_SIZE).min(buffer.len());
+        info!("Resuming {} at offset {}", name, offset);
+        let crc = if self.use_crc { Some(crc16(CRC_INIT, &buffer[..offset])) } else { None };
+        if let Some(stream) = &mut self.stream {
+            let mut block = Block::resume(name.as_bytes(), offset as u32, crc);
+            if self.long_names {
+                block = block.with_long_name();
+            }
+            // Receiver checks the data it has against the CRC before the ACK
+            let mut timeouts = self.timeouts;
+            if crc.is_some() {
+                let check = Duration::from_millis(offset as u64 * 1000 / RESUME_CHECK_RATE);
+                timeouts.ack = timeouts.ack.map(|ack| ack + check);
+            }
+            send_block(stream, &mut self.pacer, &block, progress.current_block, &timeouts).await?;
+        }
+
+        let mut builder = BlockBuilder::resume(progress.current_block, self.compression);
+        if let Some(crc) = crc {
+            builder = builder.with_crc(crc);
+        }
+        let buffer = buffer[offset..].to_vec();
+
+        Ok(self.send(Pipeline::start(buffer, builder, progress)))
+    }
+}
+
+/// Reads everything `reader` has.
+async fn read_all(mut reader: impl AsyncRead + Unpin) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await?;
+    Ok(buffer)
+}
+
+/// Checks that `name` fits into the long header, or into a block of its own
+/// with `long_names`.
+fn check_name(name: &str, long_names: bool) -> Result<(), Error> {
+    let max_len = if long_names { MAX_LONG_NAME_LEN } else { MAX_SHORT_NAME_LEN };
+    if name.len() > max_len {
+        return Err(Error::Name(name.to_string(), max_len));
+    }
+    Ok(())
+}
+
+fn prepare(
+    name: &str,
+    buffer: Vec<u8>,
+    compression: Compression,
+    use_crc: bool,
+    long_names: bool,
+    collision: Option<Collision>,
+) -> Result<Pipeline, Error> {
+    check_name(name, long_names)?;
+
+    let progress = Progress {
+        current_block: 0,
+        blocks_num: buffer.chunks(CHUNK_SIZE).len(),
+        total_bytes: buffer.len(),
+        sent_bytes: 0,
+        compressed_bytes: 0,
+        crc: None,
+        quick_saved: 0,
+        optimal_saved: 0,
+        decision: None,
+        committed: false,
+    };
+    // Long name opens the file in a block of its own, the data follows it.
+    // So does an empty file, it has no data to go with the long header
+    let (mut builder, opening) = if long_names || buffer.is_empty() {
+        (BlockBuilder::resume(0, compression), Some(Block::open(name.as_bytes(), collision).with_long_name()))
+    } else {
+        (BlockBuilder::new(name.into(), compression), None)
+    };
+    if let Some(collision) = collision {
+        builder = builder.with_collision(collision);
+    }
+    if use_crc {
+        builder = builder.with_crc(CRC_INIT);
+    }
+
+    let mut pipeline = Pipeline::start(buffer, builder, progress);
+    pipeline.opening = opening;
+    Ok(pipeline)
+}
+
+/// File transfer in progress.
+///
+/// Call `next` until it returns `None` to send the whole file.
+#[derive(Debug)]
+pub struct Upload<'a> {
+    stream: Option<&'a mut TcpStream>,
+    pacer: &'a mut Pacer,
+    timeouts: Timeouts,
+    window: usize,
+    pipeline: Pipeline,
+    /// Blocks sent but not acknowledged yet, oldest first
+    in_flight: VecDeque<Block>,
+    /// Bytes acknowledged past the last acknowledged block
+    acked: usize,
+    /// End block was sent and acknowledged
+    ended: bool,
+    /// What went over the wire during the last call to `next`
+    events: Vec<UploadEvent>,
+}
+
+impl Upload<'_> {
+    pub fn progress(&self) -> &Progress {
+        self.pipeline.progress()
+    }
+
+    /// Blocks sent and ACKs received during the last call to `next`, ACKs
+    /// of the blocks opening and ending the file are only reflected in
+    /// `Progress`.
+    pub fn events(&self) -> &[UploadEvent] {
+        &self.events
+    }
+
+    /// Sends blocks until there are `window` of them in flight and waits
+    /// until the receiver acknowledges the oldest one. Once the last one is
+    /// acknowledged, ends the file and waits until the receiver commits it.
+    ///
+    /// Returns `None` once all the blocks were sent and acknowledged.
+    pub async fn next(&mut self) -> Result<Option<Progress>, Error> {
+        self.events.clear();
+        if let Some(block) = self.pipeline.opening.take() {
+            if let Some(stream) = self.stream.as_deref_mut() {
+                let acks = send_block(stream, self.pacer, &block, 0, &self.timeouts).await?;
+                self.pipeline.progress.decision = acks.iter().rev().find_map(Ack::decision);
+            }
+            self.events.push(sent(&block, 0));
+            self.pipeline.progress.compressed_bytes += block.wire_len();
+        }
+
+        while self.in_flight.len() < self.window {
+            let Some(block) = self.pipeline.next().await? else {
+                break;
+            };
+            if let Some(stream) = self.stream.as_deref_mut() {
+                let index = self.pipeline.progress.current_block + self.in_flight.len();
+                write_block(stream, self.pacer, &block, index, &self.timeouts).await?;
+            }
+            self.events.push(sent(&block, self.pipeline.progress.current_block + self.in_flight.len()));
+            self.in_flight.push_back(block);
+        }
+
+        let Some(oldest) = self.in_flight.front() else {
+            // Empty file, or one resumed after all of it was acknowledged
+            let progress = &self.pipeline.progress;
+            if !self.ended && progress.current_block == progress.blocks_num {
+                self.end().await?;
+                return Ok(Some(self.pipeline.progress.clone()));
+            }
+            return Ok(None);
+        };
+        let (wire_len, seq) = (oldest.wire_len(), oldest.seq());
+        let index = self.pipeline.progress.current_block;
+        // The timeout covers all the ACKs of the block, not each of them
+        let deadline = self.timeouts.ack.map(|ack| Instant::now() + ack);
+        while self.acked < wire_len {
+            let Some(stream) = self.stream.as_deref_mut() else {
+                self.acked = wire_len;
+                break;
+            };
+            let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let ack = timeout(left, read_ack(stream))
+                .await
+                .ok_or_else(|| stalled(Stall::Ack, self.timeouts.ack, seq, index))??;
+            self.events.push(UploadEvent::Ack(ack));
+            // Receiver gives up after reporting an error
+            if let Some(error) = ack.failure() {
+                return Err(self.receiver_error(error, ack.seq));
+            }
+            if let Some(decision) = ack.decision() {
+                self.pipeline.progress.decision = Some(decision);
+            }
+            self.acked += ack.size as usize;
+            if let Some(expected) = self.acked.checked_sub(1).and_then(|offset| self.seq_at(offset))
+                && ack.seq != expected {
+                info!("Got out of order ACK: {} instead of {}", ack.seq, expected);
+            }
+        }
+
+        let block = self.in_flight.pop_front().unwrap();
+        self.acked -= wire_len;
+
+        let progress = &mut self.pipeline.progress;
+        progress.current_block += 1;
+        progress.sent_bytes += block.raw_len;
+        progress.compressed_bytes += block.wire_len();
+        progress.crc = block.crc;
+        let saved = block.raw_len - block.payload.len();
+        match block.compressed_with {
+            Compression::Quick => progress.quick_saved += saved,
+            Compression::Optimal => progress.optimal_saved += saved,
+            _ => {}
+        }
+        if progress.current_block == progress.blocks_num {
+            self.end().await?;
+        }
+
+        Ok(Some(self.pipeline.progress.clone()))
+    }
+
+    /// Sends the end block and waits until the receiver commits the file.
+    async fn end(&mut self) -> Result<(), Error> {
+        self.ended = true;
+        let progress = &mut self.pipeline.progress;
+        let block = Block::end(progress.total_bytes as u32);
+        if let Some(stream) = self.stream.as_deref_mut() {
+            let acks = send_block(stream, self.pacer, &block, progress.blocks_num, &self.timeouts).await?;
+            progress.committed = acks.iter().any(Ack::committed);
+            if !progress.committed {
+                info!("Receiver didn't confirm the file was written");
+            }
+        }
+        progress.compressed_bytes += block.wire_len();
+        Ok(())
+    }
+
+    /// Sequence of the block in flight that holds byte `offset`, counting
+    /// from the oldest one.
+    fn seq_at(&self, mut offset: usize) -> Option<u8> {
+        for block in &self.in_flight {
+            if offset < block.wire_len() {
+                return Some(block.seq());
+            }
+            offset -= block.wire_len();
+        }
+        None
+    }
+
+    /// Error reported for the block in flight with sequence `seq`. There are
+    /// never more than `MAX_WINDOW` of them, so the sequence is unique.
+    /// The position is left out if none of them has it.
+    fn receiver_error(&self, error: AckError, seq: u8) -> Error {
+        let index = self.in_flight.iter()
+            .position(|block| block.seq() == seq)
+            .map(|position| self.pipeline.progress.current_block + position);
+        Error::Receiver(ReceiverError {
+            error,
+            block: index,
+            seq,
+            offset: index.map(|index| index * CHUNK_SIZE),
+        })
+    }
+}
+
+fn sent(block: &Block, index: usize) -> UploadEvent {
+    UploadEvent::Sent {
+        index,
+        seq: block.seq(),
+        raw_len: block.raw_len,
+        wire_len: block.wire_len(),
+        compressed_with: block.compressed_with,
+    }
+}
+
+fn stalled(stall: Stall, after: Option<Duration>, seq: u8, index: usize) -> Error {
+    Error::Timeout(TimeoutError {
+        stall,
+        after: after.unwrap_or_default(),
+        block: index,
+        seq,
+        offset: index * CHUNK_SIZE,
+    })
+}
+
+/// Sends `block` once `pacer` lets it through, `index` is the index of the
+/// block in the file. The time it's held back doesn't count against the
+/// write timeout.
+async fn write_block(stream: &mut TcpStream, pacer: &mut Pacer, block: &Block, index: usize, timeouts: &Timeouts)
+    -> Result<(), Error> {
+    pacer.wait(block.wire_len()).await;
+    timeout(timeouts.write, stream.write_all(&block.to_bytes()))
+        .await
+        .ok_or_else(|| stalled(Stall::Write, timeouts.write, block.seq(), index))??;
+    Ok(())
+}
+
+async fn read_ack(stream: &mut TcpStream) -> Result<Ack, Error> {
+    let mut read_buf = [0u8; ACK_LEN];
+    stream.read_exact(&mut read_buf).await?;
+    Ok(Ack::decode(&read_buf))
+}
+
+/// Sends `block` and waits until the receiver acknowledges it, `index` is the
+/// index of the block in the file. Returns the ACKs of the block, which may
+/// carry the decision on the file it opened or the commit of the one it
+/// ended.
+async fn send_block(stream: &mut TcpStream, pacer: &mut Pacer, block: &Block, index: usize, timeouts: &Timeouts)
+    -> Result<Vec<Ack>, Error> {
+    write_block(stream, pacer, block, index, timeouts).await?;
+    timeout(timeouts.ack, wait_ack(stream, block, index))
+        .await
+        .ok_or_else(|| stalled(Stall::Ack, timeouts.ack, block.seq(), index))?
+}
+
+/// Waits until the receiver acknowledges all of `block`.
+async fn wait_ack(stream: &mut TcpStream, block: &Block, index: usize) -> Result<Vec<Ack>, Error> {
+    let mut acked = 0;
+    let mut acks = Vec::new();
+    loop {
+        let ack = read_ack(stream).await?;
+        // Receiver gives up after reporting an error, no matter which
+        // block caused it
+        if let Some(error) = ack.failure() {
+            return Err(Error::Receiver(ReceiverError {
+                error,
+                block: Some(index),
+                seq: ack.seq,
+                offset: Some(index * CHUNK_SIZE),
+            }));
+        }
+        // Count it anyway, like `Upload::next` does, the size is what tells
+        // when the block is through
+        if ack.seq != block.seq() {
+            info!("Got out of order ACK: {} instead of {}", ack.seq, block.seq());
+        }
+        acked += ack.size as usize;
+        acks.push(ack);
+        if acked >= block.wire_len() {
+            return Ok(acks);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the files are checked.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Time a file has to stay the same after a change before it's reported, so
+/// a file that is still being written isn't sent half done.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// What a file looked like when it was checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl Stamp {
+    /// Stamp of `path`, None if it doesn't exist or isn't a file.
+    pub fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok().filter(|metadata| metadata.is_file())?;
+        Some(Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Polls files for changes, to send them again whenever they are rebuilt.
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    /// Stamps of the files as they were last reported
+    stamps: Vec<Option<Stamp>>,
+    pub debounce: Duration,
+}
+
+impl Watcher {
+    /// Starts watching `paths`, only the changes made from now on are
+    /// reported.
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>, debounce: Duration) -> Watcher {
+        let paths: Vec<PathBuf> = paths.into_iter().collect();
+        let stamps = paths.iter().map(|path| Stamp::of(path)).collect();
+        Watcher { paths, stamps, debounce }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    fn poll(&self) -> Vec<Option<Stamp>> {
+        self.paths.iter().map(|path| Stamp::of(path)).collect()
+    }
+
+    /// Waits until some of the files change and then stay the same for
+    /// `debounce`, returns their paths. A file that is deleted is reported
+    /// once it's back, build tools often delete the output before writing it.
+    pub async fn changed(&mut self) -> Vec<PathBuf> {
+        let mut last = self.poll();
+        let mut settled = Instant::now();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let stamps = self.poll();
+            if stamps != last {
+                last = stamps;
+                settled = Instant::now();
+                continue;
+            }
+            if settled.elapsed() < self.debounce {
+                continue;
+            }
+
+            let changed: Vec<PathBuf> = self.paths.iter()
+                .zip(self.stamps.iter().zip(&stamps))
+                .filter(|(_, (seen, stamp))| stamp.is_some() && seen != stamp)
+                .map(|(path, _)| path.clone())
+                .collect();
+            if !changed.is_empty() {
+                self.stamps = stamps;
+                return changed;
+            }
+        }
+    }
+}
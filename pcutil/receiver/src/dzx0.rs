@@ -0,0 +1,201 @@
+//! ZX0 decoder, a port of the "standard" Z80 decoder by Einar Saukas &
+//! Urusergi used by the dot command.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Compressed data ended before the end marker.
+    Truncated,
+    /// Back reference points before the start of the decompressed data.
+    Offset(usize),
+    /// Elias gamma code is too long to be valid.
+    Length,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "compressed data is truncated"),
+            Error::Offset(offset) => write!(f, "offset {} points outside of the block", offset),
+            Error::Length => write!(f, "invalid length"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bits: u8,
+    mask: u8,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        let byte = *self.input.get(self.pos).ok_or(Error::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bit(&mut self) -> Result<bool, Error> {
+        if self.mask == 0 {
+            self.bits = self.byte()?;
+            self.mask = 0x80;
+        }
+        let bit = self.bits & self.mask != 0;
+        self.mask >>= 1;
+        Ok(bit)
+    }
+
+    /// Reads interlaced Elias gamma code, `stop` is the first control bit
+    /// if it was already read.
+    fn elias(&mut self, invert: bool, stop: Option<bool>) -> Result<usize, Error> {
+        let mut value = 1;
+        let mut stop = match stop {
+            Some(stop) => stop,
+            None => self.bit()?,
+        };
+        while !stop {
+            let bit = self.bit()? != invert;
+            value = value << 1 | bit as usize;
+            if value > 0x10000 {
+                return Err(Error::Length);
+            }
+            stop = self.bit()?;
+        }
+        Ok(value)
+    }
+}
+
+fn copy(output: &mut Vec<u8>, start: usize, offset: usize, len: usize) -> Result<(), Error> {
+    if offset == 0 || offset > output.len() - start {
+        return Err(Error::Offset(offset));
+    }
+    for _ in 0..len {
+        output.push(output[output.len() - offset]);
+    }
+    Ok(())
+}
+
+/// Decompresses `input` appending the result to `output`, returns the
+/// number of bytes appended.
+///
+/// Back references may only point into the data decompressed by this call.
+pub fn decompress(input: &[u8], output: &mut Vec<u8>) -> Result<usize, Error> {
+    let start = output.len();
+    let mut reader = Reader {
+        input,
+        pos: 0,
+        bits: 0,
+        mask: 0,
+    };
+    let mut last_offset = 1;
+
+    loop {
+        // Copy literals
+        let len = reader.elias(false, None)?;
+        for _ in 0..len {
+            output.push(reader.byte()?);
+        }
+
+        // Copy from last offset or new offset?
+        if !reader.bit()? {
+            let len = reader.elias(false, None)?;
+            copy(output, start, last_offset, len)?;
+
+            // Copy from literals or new offset?
+            if !reader.bit()? {
+                continue;
+            }
+        }
+
+        loop {
+            let msb = reader.elias(true, None)?;
+            if msb == 256 {
+                // End marker
+                return Ok(output.len() - start);
+            }
+            if msb > 256 {
+                return Err(Error::Length);
+            }
+            // Last offset bit becomes first length bit
+            let lsb = reader.byte()?;
+            last_offset = msb * 128 - (lsb >> 1) as usize;
+            let len = reader.elias(false, Some(lsb & 1 != 0))? + 1;
+            copy(output, start, last_offset, len)?;
+
+            // Copy from literals or new offset?
+            if !reader.bit()? {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text with repeats close and far apart, like most files sent.
+    fn sample(len: usize) -> Vec<u8> {
+        (0..).flat_map(|i: usize| format!("{} line {}\n", "x".repeat(i % 13), i * 37 % 101).into_bytes())
+            .take(len)
+            .collect()
+    }
+
+    fn compress(data: &[u8], quick: bool, backwards: bool) -> Vec<u8> {
+        zx0::Compressor::new().quick_mode(quick).backwards_mode(backwards).compress(data).output
+    }
+
+    fn round_trip(data: &[u8], quick: bool) {
+        let mut output = Vec::new();
+        assert_eq!(decompress(&compress(data, quick, false), &mut output), Ok(data.len()));
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn quick() {
+        round_trip(&sample(1024), true);
+    }
+
+    #[test]
+    fn optimal() {
+        round_trip(&sample(1024), false);
+    }
+
+    #[test]
+    fn partial_last_blocks() {
+        for len in [1, 2, 100, 1023] {
+            round_trip(&sample(len), false);
+            round_trip(&sample(len), true);
+        }
+    }
+
+    #[test]
+    fn appends_to_output() {
+        let data = sample(1024);
+        let mut output = b"before".to_vec();
+        assert_eq!(decompress(&compress(&data, false, false), &mut output), Ok(data.len()));
+        assert_eq!(&output[..6], b"before");
+        assert_eq!(&output[6..], data);
+    }
+
+    #[test]
+    fn truncated() {
+        let compressed = compress(&sample(1024), false, false);
+        let mut output = Vec::new();
+        assert_eq!(decompress(&compressed[..compressed.len() - 1], &mut output), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn backwards_is_not_supported() {
+        // The dot command only has the forward decoder, so the sender must
+        // never use backwards mode
+        let data = sample(1024);
+        let mut output = Vec::new();
+        let decoded = decompress(&compress(&data, false, true), &mut output);
+        assert!(decoded.is_err() || output != data);
+    }
+}
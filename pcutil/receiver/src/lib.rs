@@ -0,0 +1,612 @@
+//! PC stand-in for the zxmit dot command.
+//!
+//! Listens for connections and processes packets the same way as
+//! `Wifi.processPacket` in `dot-command/modules/wifi.asm` does, so the
+//! clients can be tested without a real WiFi equipped ZX Spectrum.
+//!
+//! The output directory stands for the current drive, the other drives are
+//! its subdirectories named after their letters.
+
+pub mod dzx0;
+
+use log::{error, info, warn};
+use std::fmt;
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use zxmit_protocol::ack::{ERROR_CRC, ERROR_DIRECTORY, ERROR_EXISTS, ERROR_FILE_SIZE, ERROR_NOT_OPENED, ERROR_OK,
+    ERROR_OPEN, ERROR_RESUME, ERROR_RUN, ERROR_SIZE_MISMATCH, ERROR_WRITE, STATUS_COMMITTED};
+use zxmit_protocol::collision::POLICY_NONE;
+use zxmit_protocol::{Ack, Collision, Decision, Header, CHUNK_SIZE, CRC_INIT, FLAGS_COMPRESSED, FLAGS_CRC, FLAGS_DIRECTORY,
+    FLAGS_LONG_HEADER, FLAGS_LONG_NAME, FLAGS_PROBE, FLAGS_RESUME, MAX_LONG_NAME_LEN, MAX_RUN_COMMAND_LEN, PACKET_END,
+    PACKET_RUN, PARENT_DIR, RESUME_PAYLOAD_LEN, ROOT_DIR, SHORT_HEADER_LEN, crc16, tilde_name};
+
+/// Size of `recv_buffer` in the dot command, the longest packet it accepts.
+pub const RECV_BUFFER_LEN: usize = CHUNK_SIZE + 32;
+/// Longest frame the ESP delivers at once, a single TCP segment.
+pub const MAX_FRAME_LEN: usize = 1460;
+/// The dot command accumulates that many packets before writing them out.
+pub const PACKETS_BUFFERED: usize = 4;
+
+#[derive(Debug)]
+pub enum PacketError {
+    /// Packet size doesn't match the header, '!' on the Spectrum screen.
+    SizeMismatch,
+    /// Short header received before the file was opened, '@' on the screen.
+    NotOpened,
+    /// Name in the packet can't be used as a file name.
+    Name(Vec<u8>),
+    /// Failed to open the file, '#' on the screen.
+    Open(io::Error),
+    /// Failed to write the file, '$' on the screen.
+    Write(io::Error),
+    /// Failed to create or enter the directory, '%' on the screen.
+    Directory(String),
+    /// Failed to reopen the file or seek in it, '&' on the screen.
+    Resume(io::Error),
+    /// CRC of the data doesn't match the one in the block, '*' on the screen.
+    Crc { expected: u16, actual: u16 },
+    /// File exists and the collision policy doesn't allow to replace it,
+    /// '=' on the screen.
+    Exists(String),
+    /// Command to run is empty or doesn't fit into the buffer, '?' on the
+    /// screen.
+    Run(String),
+    /// File size doesn't match the one in the end packet, '-' on the screen.
+    FileSize { expected: u32, actual: usize },
+    Decompress(dzx0::Error),
+}
+
+impl PacketError {
+    /// Error code reported in the ACK, if the dot command can detect it.
+    pub fn code(&self) -> Option<u8> {
+        match self {
+            PacketError::SizeMismatch => Some(ERROR_SIZE_MISMATCH),
+            PacketError::NotOpened => Some(ERROR_NOT_OPENED),
+            PacketError::Name(_) | PacketError::Open(_) => Some(ERROR_OPEN),
+            PacketError::Write(_) => Some(ERROR_WRITE),
+            PacketError::Directory(_) => Some(ERROR_DIRECTORY),
+            PacketError::Resume(_) => Some(ERROR_RESUME),
+            PacketError::Crc { .. } => Some(ERROR_CRC),
+            PacketError::Exists(_) => Some(ERROR_EXISTS),
+            PacketError::Run(_) => Some(ERROR_RUN),
+            PacketError::FileSize { .. } => Some(ERROR_FILE_SIZE),
+            // Spectrum can't tell broken compressed data from the valid one
+            PacketError::Decompress(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::SizeMismatch => write!(f, "packet size doesn't match the header"),
+            PacketError::NotOpened => write!(f, "short header received before the file was opened"),
+            PacketError::Name(name) => write!(f, "invalid file name {:?}", String::from_utf8_lossy(name)),
+            PacketError::Open(e) => write!(f, "failed to open the file: {}", e),
+            PacketError::Write(e) => write!(f, "failed to write the file: {}", e),
+            PacketError::Directory(e) => write!(f, "failed to enter the directory: {}", e),
+            PacketError::Resume(e) => write!(f, "failed to resume the file: {}", e),
+            PacketError::Crc { expected, actual } => write!(f, "CRC mismatch: expected {:#06x}, got {:#06x}",
+                expected, actual),
+            PacketError::Exists(name) => write!(f, "{} exists", name),
+            PacketError::Run(command) => write!(f, "can't run {:?}", command),
+            PacketError::FileSize { expected, actual } => write!(f, "file is {} bytes instead of {}",
+                actual, expected),
+            PacketError::Decompress(e) => write!(f, "failed to decompress the block: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+/// State of a single connection, mirrors the variables of the dot command.
+#[derive(Debug)]
+pub struct Session {
+    out_dir: PathBuf,
+    /// Drive selected by the client, the current one if it's `None`
+    drive: Option<char>,
+    /// Current directory, relative to the root of the drive
+    cwd: PathBuf,
+    file: Option<File>,
+    name: String,
+    /// Packet received so far, it may span several frames
+    packet: Vec<u8>,
+    /// Sequence of the last packet that was started
+    seq: u8,
+    /// Anything but probes was received
+    used: bool,
+    data_buffer: Vec<u8>,
+    packets_buffered: usize,
+    written: usize,
+    /// CRC of the file data received so far
+    crc: u16,
+    /// Decision on the file opened by the last frame, reported in its ACK
+    decision: Option<Decision>,
+    /// File ended by the last frame was committed, reported in its ACK
+    committed: bool,
+    /// Command to run once the connection is closed
+    run: Option<String>,
+}
+
+impl Session {
+    pub fn new(out_dir: &Path) -> Session {
+        Session {
+            out_dir: out_dir.to_path_buf(),
+            drive: None,
+            cwd: PathBuf::new(),
+            file: None,
+            name: String::new(),
+            packet: Vec::with_capacity(RECV_BUFFER_LEN),
+            seq: 0,
+            used: false,
+            data_buffer: Vec::with_capacity(PACKETS_BUFFERED * CHUNK_SIZE),
+            packets_buffered: 0,
+            written: 0,
+            crc: CRC_INIT,
+            decision: None,
+            committed: false,
+            run: None,
+        }
+    }
+
+    fn valid_name(name: &[u8]) -> Option<&str> {
+        std::str::from_utf8(name)
+            .ok()
+            .filter(|name| !name.is_empty() && name.len() <= MAX_LONG_NAME_LEN)
+            .filter(|name| *name != "." && *name != "..")
+            .filter(|name| !name.contains(['/', '\\', ':']))
+    }
+
+    /// Drive letter if `name` is one followed by a colon.
+    fn drive_name(name: &[u8]) -> Option<char> {
+        match name {
+            [letter, b':'] if letter.is_ascii_alphabetic() => Some(letter.to_ascii_uppercase() as char),
+            _ => None,
+        }
+    }
+
+    /// Directory the root of the selected drive is in.
+    fn root(&self) -> PathBuf {
+        match self.drive {
+            Some(drive) => self.out_dir.join(drive.to_string()),
+            None => self.out_dir.clone(),
+        }
+    }
+
+    fn file_path(&self, name: &[u8]) -> Result<PathBuf, PacketError> {
+        match Self::valid_name(name) {
+            Some(name) => Ok(self.root().join(&self.cwd).join(name)),
+            None => Err(PacketError::Name(name.to_vec())),
+        }
+    }
+
+    /// Creates directory `name` unless it exists and enters it, `..` goes
+    /// one level up, `/` to the root and a drive letter with a colon selects
+    /// the drive.
+    async fn change_dir(&mut self, name: &[u8]) -> Result<(), PacketError> {
+        if name == PARENT_DIR.as_bytes() {
+            if !self.cwd.pop() {
+                return Err(PacketError::Directory("already at the top".to_string()));
+            }
+            return Ok(());
+        }
+        if name == ROOT_DIR.as_bytes() {
+            self.cwd.clear();
+            return Ok(());
+        }
+        if let Some(drive) = Self::drive_name(name) {
+            let root = self.out_dir.join(drive.to_string());
+            tokio::fs::create_dir_all(&root)
+                .await
+                .map_err(|e| PacketError::Directory(format!("{}: {}", root.display(), e)))?;
+            info!("Selected drive {}", drive);
+            self.drive = Some(drive);
+            self.cwd.clear();
+            return Ok(());
+        }
+
+        let name = Self::valid_name(name)
+            .ok_or_else(|| PacketError::Directory(String::from_utf8_lossy(name).to_string()))?;
+        let path = self.root().join(&self.cwd).join(name);
+        match tokio::fs::create_dir(&path).await {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists || !path.is_dir() => {
+                return Err(PacketError::Directory(format!("{}: {}", path.display(), e)));
+            }
+            _ => {}
+        }
+        info!("Entering {}", path.display());
+        self.cwd.push(name);
+        Ok(())
+    }
+
+    /// Creates file `name`, `policy` tells what to do if it exists and
+    /// `long_names` whether it came in a long name packet. Returns the name
+    /// the file was saved under.
+    async fn create(&mut self, name: &[u8], policy: u8, long_names: bool) -> Result<String, PacketError> {
+        let path = self.file_path(name)?;
+        let name = String::from_utf8_lossy(name).to_string();
+        if policy == POLICY_NONE {
+            self.file = Some(File::create(&path).await.map_err(PacketError::Open)?);
+            return Ok(name);
+        }
+
+        let exists = match File::create_new(&path).await {
+            Ok(file) => {
+                self.file = Some(file);
+                self.decision = Some(Decision::Created);
+                return Ok(name);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => PacketError::Exists(path.display().to_string()),
+            Err(e) => return Err(PacketError::Open(e)),
+        };
+        // Unknown policies are taken for overwrite, like the dot command does
+        match Collision::from_code(policy).unwrap_or_default() {
+            Collision::Overwrite => {
+                self.file = Some(File::create(&path).await.map_err(PacketError::Open)?);
+                self.decision = Some(Decision::Overwritten);
+                Ok(name)
+            }
+            Collision::Fail => Err(exists),
+            Collision::Rename => {
+                for n in 1..=9 {
+                    let renamed = tilde_name(&name, n, long_names);
+                    let path = path.with_file_name(&renamed);
+                    match File::create_new(&path).await {
+                        Ok(file) => {
+                            self.file = Some(file);
+                            self.decision = Some(Decision::Renamed(n));
+                            return Ok(renamed);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                        Err(e) => return Err(PacketError::Open(e)),
+                    }
+                }
+                Err(exists)
+            }
+        }
+    }
+
+    /// Opens existing file `name` for writing at `offset`.
+    async fn reopen(&mut self, name: &[u8], offset: u64) -> Result<(), PacketError> {
+        let path = self.file_path(name)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .await
+            .map_err(PacketError::Resume)?;
+        let len = file.metadata().await.map_err(PacketError::Resume)?.len();
+        if len < offset {
+            let e = io::Error::new(io::ErrorKind::UnexpectedEof, format!("file is only {} bytes long", len));
+            return Err(PacketError::Resume(e));
+        }
+        file.seek(SeekFrom::Start(offset)).await.map_err(PacketError::Resume)?;
+
+        info!("Resuming {} at offset {}", path.display(), offset);
+        self.file = Some(file);
+        self.name = String::from_utf8_lossy(name).to_string();
+        self.written = offset as usize;
+        Ok(())
+    }
+
+    /// CRC of the first `len` bytes of the reopened file, which is left at
+    /// `len` again.
+    async fn prefix_crc(&mut self, len: u64) -> Result<u16, PacketError> {
+        let file = self.file.as_mut().ok_or(PacketError::NotOpened)?;
+        file.seek(SeekFrom::Start(0)).await.map_err(PacketError::Resume)?;
+        let mut crc = CRC_INIT;
+        // Read through the data buffer, like the dot command does
+        let mut buffer = vec![0u8; PACKETS_BUFFERED * CHUNK_SIZE];
+        let mut left = len;
+        while left > 0 {
+            let size = left.min(buffer.len() as u64) as usize;
+            let chunk = &mut buffer[..size];
+            file.read_exact(chunk).await.map_err(PacketError::Resume)?;
+            crc = crc16(crc, chunk);
+            left -= chunk.len() as u64;
+        }
+        Ok(crc)
+    }
+
+    /// Processes a frame of data as it was delivered by the ESP and returns
+    /// the ACK for it. The frame may hold several packets or a part of one,
+    /// which is completed by the next frames.
+    ///
+    /// On error the rest of the frame is dropped, the ACK should carry the
+    /// sequence from `seq`.
+    pub async fn process_frame(&mut self, frame: &[u8]) -> Result<Ack, PacketError> {
+        let mut rest = frame;
+        while !rest.is_empty() {
+            let want = Header::peek_packet_len(&self.packet).unwrap_or(SHORT_HEADER_LEN);
+            if want > RECV_BUFFER_LEN {
+                return Err(PacketError::SizeMismatch);
+            }
+            let (chunk, tail) = rest.split_at((want - self.packet.len()).min(rest.len()));
+            self.packet.extend_from_slice(chunk);
+            self.seq = self.packet[0];
+            rest = tail;
+
+            if Header::peek_packet_len(&self.packet) == Some(self.packet.len()) {
+                let packet = std::mem::take(&mut self.packet);
+                self.process_packet(&packet).await?;
+                self.packet = packet;
+                self.packet.clear();
+            }
+        }
+
+        let status = self.decision.take().map_or(ERROR_OK, Ack::status);
+        Ok(Ack {
+            seq: self.seq,
+            error: if std::mem::take(&mut self.committed) { STATUS_COMMITTED } else { status },
+            size: frame.len() as u16,
+        })
+    }
+
+    /// Sequence of the packet the last frame ended in.
+    pub fn seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Processes a single complete packet.
+    pub async fn process_packet(&mut self, packet: &[u8]) -> Result<(), PacketError> {
+        let header = Header::parse(packet).ok_or(PacketError::SizeMismatch)?;
+        if header.packet_len() != packet.len() {
+            return Err(PacketError::SizeMismatch);
+        }
+        let payload_end = header.encoded_len() + header.size as usize;
+        let trailer = &packet[payload_end..];
+
+        if header.flags & FLAGS_PROBE != 0 {
+            info!("Probed");
+            return Ok(());
+        }
+        self.used = true;
+
+        if let Some(control) = header.control {
+            let payload = &packet[header.encoded_len()..payload_end];
+            return match control {
+                PACKET_END => {
+                    // File size takes the whole payload
+                    let size = payload.get(..RESUME_PAYLOAD_LEN)
+                        .and_then(|size| size.try_into().ok())
+                        .map(u32::from_le_bytes)
+                        .ok_or(PacketError::SizeMismatch)?;
+                    self.end(size).await
+                }
+                PACKET_RUN => {
+                    // Command line to run takes the whole payload
+                    self.close().await.map_err(PacketError::Write)?;
+                    let command = String::from_utf8_lossy(payload).to_string();
+                    if command.is_empty() || command.len() > MAX_RUN_COMMAND_LEN {
+                        return Err(PacketError::Run(command));
+                    }
+                    self.run = Some(command);
+                    Ok(())
+                }
+                // Unknown control packet, like the dot command does
+                _ => Err(PacketError::SizeMismatch),
+            };
+        }
+
+        // Long name ends the payload, after the offset of a resume packet or
+        // the collision policy of the one opening the file
+        let mut policy = header.policy;
+        let name = if header.flags & FLAGS_LONG_NAME != 0 {
+            let skip = if header.flags & FLAGS_RESUME != 0 {
+                RESUME_PAYLOAD_LEN
+            } else if header.flags & FLAGS_DIRECTORY == 0 {
+                policy = *packet.get(header.encoded_len()).ok_or(PacketError::SizeMismatch)?;
+                1
+            } else {
+                0
+            };
+            packet.get(header.encoded_len() + skip..payload_end).ok_or(PacketError::SizeMismatch)?
+        } else {
+            header.name.as_deref().unwrap_or_default()
+        };
+
+        if header.flags & FLAGS_DIRECTORY != 0 {
+            self.close().await.map_err(PacketError::Write)?;
+            self.change_dir(name).await?;
+            return Ok(());
+        }
+
+        if header.flags & FLAGS_RESUME != 0 {
+            self.close().await.map_err(PacketError::Write)?;
+            let offset = packet[header.encoded_len()..payload_end]
+                .get(..RESUME_PAYLOAD_LEN)
+                .and_then(|offset| offset.try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or(PacketError::SizeMismatch)?;
+            self.reopen(name, offset as u64).await?;
+            if header.flags & FLAGS_CRC != 0 {
+                // Data already written is checked against the CRC of the
+                // client too, so the CRC covers the whole resumed file
+                let expected = u16::from_le_bytes([trailer[0], trailer[1]]);
+                let actual = self.prefix_crc(offset as u64).await?;
+                if actual != expected {
+                    return Err(PacketError::Crc { expected, actual });
+                }
+                self.crc = actual;
+            }
+            return Ok(());
+        }
+
+        if header.flags & (FLAGS_LONG_HEADER | FLAGS_LONG_NAME) != 0 {
+            // Long header starts the next file
+            self.close().await.map_err(PacketError::Write)?;
+            self.name = self.create(name, policy, header.flags & FLAGS_LONG_NAME != 0).await?;
+            self.crc = CRC_INIT;
+            match self.decision {
+                Some(decision) => info!("Receiving {}, {}", self.name, decision),
+                None => info!("Receiving {}", self.name),
+            }
+            // Long name takes the whole payload, the data follows it
+            if header.flags & FLAGS_LONG_NAME != 0 {
+                return Ok(());
+            }
+        } else if self.file.is_none() {
+            return Err(PacketError::NotOpened);
+        }
+
+        let payload = &packet[header.encoded_len()..payload_end];
+        let start = self.data_buffer.len();
+        if header.flags & FLAGS_COMPRESSED != 0 {
+            let len = dzx0::decompress(payload, &mut self.data_buffer)
+                .map_err(PacketError::Decompress)?;
+            // Compressed block unpacks into CHUNK_SIZE bytes, unless the
+            // header says otherwise, anything else means it's broken
+            let expected = header.length.map_or(CHUNK_SIZE, usize::from);
+            if len != expected {
+                warn!("Block {} unpacks into {} bytes instead of {}", header.seq, len, expected);
+                self.data_buffer.truncate(start);
+                return Err(PacketError::SizeMismatch);
+            }
+        } else {
+            self.data_buffer.extend_from_slice(payload);
+        }
+
+        if header.flags & FLAGS_CRC != 0 {
+            self.crc = crc16(self.crc, &self.data_buffer[start..]);
+            let expected = u16::from_le_bytes([trailer[0], trailer[1]]);
+            if self.crc != expected {
+                return Err(PacketError::Crc { expected, actual: self.crc });
+            }
+        }
+
+        self.packets_buffered = (self.packets_buffered + 1) % PACKETS_BUFFERED;
+        if self.packets_buffered == 0 {
+            self.flush().await.map_err(PacketError::Write)?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_all(&self.data_buffer).await?;
+            self.written += self.data_buffer.len();
+        }
+        self.data_buffer.clear();
+        Ok(())
+    }
+
+    /// Writes out the buffered data, checks that the file is `size` bytes
+    /// long and closes it.
+    async fn end(&mut self, size: u32) -> Result<(), PacketError> {
+        if self.file.is_none() {
+            return Err(PacketError::NotOpened);
+        }
+        self.flush().await.map_err(PacketError::Write)?;
+        if self.written != size as usize {
+            return Err(PacketError::FileSize { expected: size, actual: self.written });
+        }
+        self.close().await.map_err(PacketError::Write)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Writes out the buffered data and closes the file, if any.
+    pub async fn close(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        self.flush().await?;
+        self.packets_buffered = 0;
+        if let Some(file) = self.file.take() {
+            file.sync_all().await?;
+            info!("Received {}, {} bytes", self.name, self.written);
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Emulated dot command.
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    /// Directory the received files are written into.
+    pub out_dir: PathBuf,
+}
+
+impl Receiver {
+    /// Accepts connections one at a time, stops after the first one that
+    /// did more than probing if `once` is set, like the dot command does.
+    pub async fn serve(&self, listener: TcpListener, once: bool) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            info!("Connection from {}", peer);
+            let done = self.handle(stream).await.unwrap_or_else(|e| {
+                error!("{}", e);
+                true
+            });
+            if once && done {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Receives a file over `stream`. Every read from the socket is treated
+    /// as a single frame, just like the ESP delivers them to the Spectrum.
+    ///
+    /// Returns false if the connection only probed the receiver, or dropped
+    /// before the file was committed and the client may come back to resume
+    /// it.
+    pub async fn handle(&self, mut stream: TcpStream) -> io::Result<bool> {
+        let mut session = Session::new(&self.out_dir);
+        let mut frame_buffer = vec![0u8; MAX_FRAME_LEN];
+
+        // ESP reports a reset connection as closed as well
+        let mut failed = false;
+        loop {
+            let data_size = match stream.read(&mut frame_buffer).await {
+                Ok(0) => break,
+                Ok(data_size) => data_size,
+                Err(e) => {
+                    warn!("Connection lost: {}", e);
+                    break;
+                }
+            };
+            let frame = &frame_buffer[..data_size];
+
+            match session.process_frame(frame).await {
+                Ok(ack) => {
+                    if let Err(e) = stream.write_all(&ack.encode()).await {
+                        warn!("Connection lost: {}", e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Protocol error: {}", e);
+                    if let Some(code) = e.code() {
+                        let ack = Ack {
+                            seq: session.seq(),
+                            error: code,
+                            size: data_size as u16,
+                        };
+                        let _ = stream.write_all(&ack.encode()).await;
+                    }
+                    failed = true;
+                    break;
+                }
+            }
+        }
+
+        let dropped = !failed && session.file.is_some();
+        if dropped {
+            info!("Connection dropped before {} was committed, waiting to resume it", session.name);
+        }
+        session.close().await?;
+        // There's nothing to run the Spectrum files with here
+        if let Some(command) = &session.run {
+            info!("Would run .{}", command);
+        }
+        Ok(session.used && !dropped)
+    }
+}
@@ -0,0 +1,52 @@
+use clap::Parser;
+use log::{error, info};
+use simple_logger::SimpleLogger;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use zxmit_protocol::DEFAULT_PORT;
+use zxmit_receiver::Receiver;
+
+const CARGO_PKG_VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+
+/// Receives files the same way zxmit dot command does on ZX Spectrum
+#[derive(Debug, Parser)]
+#[command(about)]
+pub struct Arguments {
+    /// Directory to save the received files into
+    #[arg(short, long, default_value = ".")]
+    pub output: PathBuf,
+    /// Address to listen on
+    #[arg(short, long, default_value = "0.0.0.0")]
+    pub address: String,
+    /// Port to listen on
+    #[arg(short, long, default_value_t = DEFAULT_PORT)]
+    pub port: u16,
+    /// Exit after the first connection that does more than probing, unless it drops in the middle of a file,
+    /// like the dot command does
+    #[arg(long)]
+    pub once: bool,
+}
+
+async fn process(args: Arguments) -> std::io::Result<()> {
+    let listener = TcpListener::bind((args.address.as_str(), args.port)).await?;
+    info!("Listening on {}", listener.local_addr()?);
+
+    Receiver { out_dir: args.output }
+        .serve(listener, args.once)
+        .await
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "zxmit-receiver {} (c) Alex Nihirash & Vasily Khoruzhick",
+        CARGO_PKG_VERSION.unwrap_or("dev")
+    );
+    let args = Arguments::parse();
+
+    SimpleLogger::new().init().unwrap();
+
+    if let Err(e) = process(args).await {
+        error!("{}", e);
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use zxmit_protocol::{AckError, Address, BlockBuilder, CHUNK_SIZE, CRC_INIT, Collision, Compression, Decision,
+    Destination, Error, Pacing, Progress, Timeouts, Upload, Uploader, compress_chunk, crc16};
+use zxmit_receiver::{PacketError, Receiver, Session};
+
+/// Empty directory of its own for `test` to receive the files into.
+fn out_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zxmit-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Text with repeats close and far apart, so some blocks compress and some
+/// don't.
+fn sample(len: usize) -> Vec<u8> {
+    (0..).flat_map(|i: usize| format!("{} {}\n", "x".repeat(i % 29), i * i % 1009).into_bytes())
+        .take(len)
+        .collect()
+}
+
+/// Starts a receiver writing into `dir`, returns its task and an uploader
+/// sending to it with `window`.
+async fn start(dir: &Path, once: bool, window: usize) -> (JoinHandle<std::io::Result<()>>, Uploader) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let receiver = Receiver { out_dir: dir.to_path_buf() };
+    let server = tokio::spawn(async move { receiver.serve(listener, once).await });
+    let uploader = Uploader {
+        address: Address { host: "127.0.0.1".to_string(), port },
+        compression: Compression::Auto,
+        use_crc: true,
+        dummy: false,
+        timeouts: Timeouts::from_secs(5, 5, 5),
+        window,
+        long_names: false,
+        destination: Destination::default(),
+        collision: None,
+        pacing: Pacing::default(),
+    };
+    (server, uploader)
+}
+
+async fn finish(upload: &mut Upload<'_>) -> Progress {
+    let mut progress = upload.progress().clone();
+    while let Some(next) = upload.next().await.unwrap() {
+        progress = next;
+    }
+    progress
+}
+
+#[tokio::test]
+async fn upload() {
+    let dir = out_dir("upload");
+    let (_server, uploader) = start(&dir, false, 1).await;
+    let data = sample(5 * CHUNK_SIZE + 300);
+
+    let mut connection = uploader.connect().await.unwrap();
+    let progress = finish(&mut connection.upload("DATA.BIN", data.clone()).unwrap()).await;
+    assert!(progress.committed);
+    assert_eq!((progress.current_block, progress.sent_bytes), (6, data.len()));
+    assert_eq!(progress.crc, Some(crc16(CRC_INIT, &data)));
+    assert_eq!(std::fs::read(dir.join("DATA.BIN")).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn window() {
+    let dir = out_dir("window");
+    let (_server, uploader) = start(&dir, false, 4).await;
+    let files = [("ONE.BIN", sample(20 * CHUNK_SIZE)), ("TWO.BIN", sample(CHUNK_SIZE - 1)), ("EMPTY.BIN", Vec::new())];
+
+    // All of them over a single connection
+    let mut connection = uploader.connect().await.unwrap();
+    for (name, data) in &files {
+        let progress = finish(&mut connection.upload(name, data.clone()).unwrap()).await;
+        assert!(progress.committed, "{} is not committed", name);
+    }
+    for (name, data) in &files {
+        assert_eq!(&std::fs::read(dir.join(name)).unwrap(), data, "{} differs", name);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn resume() {
+    let dir = out_dir("resume");
+    std::fs::write(dir.join("GAME.BIN"), b"taken").unwrap();
+    let (server, mut uploader) = start(&dir, true, 2).await;
+    uploader.collision = Some(Collision::Rename);
+    let data = sample(10 * CHUNK_SIZE + 100);
+
+    // Connection drops after a few blocks
+    let mut connection = uploader.connect().await.unwrap();
+    let mut upload = connection.upload("GAME.BIN", data.clone()).unwrap();
+    let mut progress = upload.progress().clone();
+    while progress.current_block < 4 {
+        progress = upload.next().await.unwrap().unwrap();
+    }
+    drop(upload);
+    drop(connection);
+    assert_eq!(progress.decision, Some(Decision::Renamed(1)));
+
+    // The receiver waits for it to come back, even with `once`
+    let mut connection = uploader.connect().await.unwrap();
+    let progress = finish(&mut connection.resume("GAME.BIN", data.clone(), progress).await.unwrap()).await;
+    assert!(progress.committed);
+    assert_eq!(progress.sent_bytes, data.len());
+    drop(connection);
+    server.await.unwrap().unwrap();
+
+    assert_eq!(std::fs::read(dir.join("GAME~1.BIN")).unwrap(), data);
+    assert_eq!(std::fs::read(dir.join("GAME.BIN")).unwrap(), b"taken");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn resume_corrupted() {
+    let dir = out_dir("resume_corrupted");
+    let (_server, uploader) = start(&dir, false, 1).await;
+    let data = sample(6 * CHUNK_SIZE);
+
+    let mut connection = uploader.connect().await.unwrap();
+    let mut upload = connection.upload("GAME.BIN", data.clone()).unwrap();
+    let mut progress = upload.progress().clone();
+    while progress.current_block < 3 {
+        progress = upload.next().await.unwrap().unwrap();
+    }
+    drop(upload);
+    drop(connection);
+
+    // A byte of what the receiver has goes bad while it waits
+    let path = dir.join("GAME.BIN");
+    while std::fs::metadata(&path).unwrap().len() < 3 * CHUNK_SIZE as u64 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let mut corrupted = std::fs::read(&path).unwrap();
+    corrupted[100] ^= 1;
+    std::fs::write(&path, corrupted).unwrap();
+
+    let mut connection = uploader.connect().await.unwrap();
+    match connection.resume("GAME.BIN", data, progress).await {
+        Err(Error::Receiver(error)) => assert_eq!(error.error, AckError::Crc),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("corrupted file resumed"),
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn wrong_length() {
+    let dir = out_dir("wrong_length");
+    let mut session = Session::new(&dir);
+    let chunk = sample(CHUNK_SIZE);
+
+    // Unpacks into fewer bytes than the header says
+    let packed = compress_chunk(Compression::Quick, &chunk[..CHUNK_SIZE - 100]);
+    assert!(packed.is_some());
+    let block = BlockBuilder::new(b"SHORT.BIN".to_vec(), Compression::Quick).build_compressed(&chunk, packed);
+    let result = session.process_packet(&block.to_bytes()).await;
+    assert!(matches!(result, Err(PacketError::SizeMismatch)), "{:?}", result);
+    session.close().await.unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -2,12 +2,18 @@
 
 mod upload;
 
-use upload::{UploadError, FileUploader, UploadProgress};
-use iced::widget::{button, center, checkbox, column, row, text, text_input, progress_bar};
-use iced::{Center, Element, Subscription, Event, Task, window::Event as WindowEvent, window};
+use upload::{UploadError, FileUploader, Source, UploadProgress};
+use iced::widget::{button, center, checkbox, column, pick_list, row, text, text_input, progress_bar};
+use iced::{Center, Element, Length, Subscription, Event, Task, window::Event as WindowEvent, window};
+use iced::task::Handle;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time;
 use serde::{Deserialize, Serialize};
+use zxmit_protocol::{Address, Collision, Compression, DEFAULT_ACK_TIMEOUT, DEFAULT_CONNECT_TIMEOUT, DEFAULT_DEBOUNCE,
+    DEFAULT_PORT, DEFAULT_PROBE_TIMEOUT, DEFAULT_WINDOW, DEFAULT_WRITE_TIMEOUT, Destination, Pacing, Timeouts, Watcher,
+    discover, filename_to_long, filename_to_short, only_attaches, remote_name, run_command};
+use zxmit_protocol::address::parse_port;
 
 const CARGO_PKG_VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
@@ -24,8 +30,9 @@ fn workarounds() {
 pub fn main() -> iced::Result {
     workarounds();
     let settings: window::settings::Settings = iced::window::settings::Settings {
-        size: iced::Size::new(450.0, 400.0),
-        resizable: (false),
+        size: iced::Size::new(520.0, 680.0),
+        min_size: Some(iced::Size::new(520.0, 680.0)),
+        resizable: (true),
         ..Default::default()
     };
     iced::application(App::new, App::update, App::view)
@@ -38,8 +45,70 @@ pub fn main() -> iced::Result {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Config {
     address: String,
+    #[serde(default = "default_port")]
+    port: u16,
+    /// Only used if `compression` is missing, configs of older versions
+    /// have just that
     use_compression: bool,
+    #[serde(default)]
+    compression: Option<String>,
     dummy: bool,
+    /// Make the Spectrum verify CRC of the data, older versions of the dot
+    /// command reject the blocks that carry it
+    #[serde(default)]
+    use_crc: bool,
+    /// Timeouts in seconds, 0 waits forever
+    #[serde(default = "default_connect_timeout")]
+    connect_timeout: u64,
+    #[serde(default = "default_write_timeout")]
+    write_timeout: u64,
+    #[serde(default = "default_ack_timeout")]
+    ack_timeout: u64,
+    /// Blocks sent ahead without waiting for their ACKs
+    #[serde(default = "default_window")]
+    window: usize,
+    /// Keep the file name instead of converting it to DOS 8.3
+    #[serde(default)]
+    long_names: bool,
+    /// Directory on the Spectrum the files go into
+    #[serde(default)]
+    destination: String,
+    /// What the Spectrum does if the file exists, older versions overwrite
+    /// it silently if it's missing
+    #[serde(default)]
+    collision: Option<String>,
+    /// Most bytes per second to send, 0 doesn't limit it
+    #[serde(default)]
+    rate: u32,
+    /// Milliseconds to pause after every block
+    #[serde(default)]
+    delay: u64,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_connect_timeout() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT.as_secs()
+}
+
+fn default_write_timeout() -> u64 {
+    DEFAULT_WRITE_TIMEOUT.as_secs()
+}
+
+fn default_ack_timeout() -> u64 {
+    DEFAULT_ACK_TIMEOUT.as_secs()
+}
+
+fn default_window() -> usize {
+    DEFAULT_WINDOW
+}
+
+/// Parses a number field, empty stands for 0
+fn parse_or_zero<T: std::str::FromStr + Default>(value: &str) -> Option<T> {
+    let value = value.trim();
+    if value.is_empty() { Some(T::default()) } else { value.parse().ok() }
 }
 
 #[derive(Debug, Clone)]
@@ -56,7 +125,7 @@ impl Config {
             None => { PathBuf::new() },
         };
 
-        dir.join(std::format!(".{}rc", env!("CARGO_CRATE_NAME")))
+        dir.join(format!(".{}rc", env!("CARGO_CRATE_NAME")))
     }
 
     async fn load_config() -> Result<Config, ConfigError> {
@@ -92,12 +161,48 @@ impl Config {
 #[derive(Debug)]
 struct App {
     filepath: Option<PathBuf>,
+    /// Clipboard contents to send instead of the file
+    pasted: Option<Vec<u8>>,
     address: Option<String>,
+    port: String,
     status: String,
     sending: bool,
+    scanning: bool,
+    /// User was told that scanning ends older versions of the dot command
+    /// and has to confirm it, asked again before every scan
+    scan_confirming: bool,
+    /// Hosts found by the last scan, if there's more than one to pick from
+    found: Vec<String>,
     dummy: bool,
-    use_compression: bool,
+    compression: Compression,
+    use_crc: bool,
+    timeouts: Timeouts,
+    window: usize,
+    long_names: bool,
+    /// Name to save the file under, derived from the file name if empty
+    save_as: String,
+    destination: String,
+    collision: Option<Collision>,
+    /// Name the last file was sent under
+    remote_name: String,
+    /// Send the file again whenever it changes
+    watch: bool,
+    /// Waits for the file to change, dropping it stops watching
+    watching: Option<Handle>,
+    /// File changed while it was being sent
+    changed: bool,
+    /// Run the file on the Spectrum once it's sent, it was sent with
+    /// "Send & run"
+    run: bool,
+    /// Bytes per second, empty doesn't limit it
+    rate: String,
+    /// Milliseconds after every block, empty doesn't pause
+    delay: String,
+    pacing: Pacing,
     progress: f32,
+    /// Progress of the last upload, to resume it if it's interrupted
+    last_progress: UploadProgress,
+    can_resume: bool,
     total_bytes: usize,
     compressed_bytes: usize,
     now: Option<time::Instant>,
@@ -107,12 +212,34 @@ impl Default for App {
     fn default() -> Self {
         Self {
             filepath: None,
+            pasted: None,
             address: None,
+            port: DEFAULT_PORT.to_string(),
             status: String::new(),
             sending: false,
+            scanning: false,
+            scan_confirming: false,
+            found: Vec::new(),
             dummy: false,
-            use_compression: true,
+            compression: Compression::default(),
+            use_crc: false,
+            timeouts: Timeouts::default(),
+            window: DEFAULT_WINDOW,
+            long_names: false,
+            save_as: String::new(),
+            destination: String::new(),
+            collision: None,
+            remote_name: String::new(),
+            watch: false,
+            watching: None,
+            changed: false,
+            run: false,
+            rate: String::new(),
+            delay: String::new(),
+            pacing: Pacing::default(),
             progress: 0f32,
+            last_progress: UploadProgress::default(),
+            can_resume: false,
             total_bytes: 0,
             compressed_bytes: 0,
             now: None,
@@ -127,15 +254,31 @@ enum Message {
     Uploading(UploadProgress),
     UploadDone(Result<(), UploadError>),
     AddressChanged(String),
+    PortChanged(String),
+    SaveAsChanged(String),
+    DestinationChanged(String),
+    CollisionChanged(Collision),
+    RateChanged(String),
+    DelayChanged(String),
     ButtonPressed,
-    UseCompressionChanged(bool),
+    ResumePressed,
+    PastePressed,
+    Pasted(Option<String>),
+    ScanPressed,
+    ScanDone(Result<Vec<SocketAddr>, String>),
+    FoundSelected(String),
+    CompressionChanged(Compression),
     DummyChanged(bool),
+    LongNamesChanged(bool),
+    WatchChanged(bool),
+    SendAndRunPressed,
+    FileChanged,
     EventOccurred(Event),
 }
 
 impl App {
     fn title(&self) -> String {
-        std::format!("ZXmit v{}  © 2025 Vasily Khoruzhick", CARGO_PKG_VERSION.unwrap())
+        format!("ZXmit v{}  © 2025 Vasily Khoruzhick", CARGO_PKG_VERSION.unwrap())
     }
 
     fn new() -> (Self, Task<Message>) {
@@ -150,8 +293,21 @@ impl App {
             Message::ConfigLoaded(Ok(config)) => {
                 *self = Self {
                     address : Some(config.address),
-                    use_compression: config.use_compression,
+                    port: config.port.to_string(),
+                    compression: match config.compression.and_then(|c| c.parse().ok()) {
+                        Some(compression) => compression,
+                        None if config.use_compression => Compression::default(),
+                        None => Compression::None,
+                    },
                     dummy: config.dummy,
+                    use_crc: config.use_crc,
+                    timeouts: Timeouts::from_secs(config.connect_timeout, config.write_timeout, config.ack_timeout),
+                    window: config.window,
+                    long_names: config.long_names,
+                    destination: config.destination,
+                    collision: config.collision.and_then(|c| c.parse().ok()),
+                    rate: if config.rate == 0 { String::new() } else { config.rate.to_string() },
+                    delay: if config.delay == 0 { String::new() } else { config.delay.to_string() },
                     ..Default::default()
                 };
                 Task::none()
@@ -174,73 +330,355 @@ impl App {
                 self.address = Some(address);
                 Task::none()
             }
+            Message::PortChanged(port) => {
+                self.port = port;
+                Task::none()
+            }
+            Message::SaveAsChanged(name) => {
+                // The interrupted file was sent under the other name
+                self.save_as = name;
+                self.can_resume = false;
+                Task::none()
+            }
+            Message::DestinationChanged(destination) => {
+                self.destination = destination;
+                self.can_resume = false;
+                Task::none()
+            }
+            Message::RateChanged(rate) => {
+                self.rate = rate;
+                Task::none()
+            }
+            Message::DelayChanged(delay) => {
+                self.delay = delay;
+                Task::none()
+            }
+            Message::CollisionChanged(collision) => {
+                if !self.sending {
+                    self.collision = Some(collision);
+                }
+                Task::none()
+            }
             Message::UploadDone(Ok(())) => {
                 self.sending = false;
-                self.status = std::format!("Upload complete\nCompressed {} into {} bytes\nRatio: {}, time: {:.2?}",
+                // Empty file has no ratio
+                self.status = if self.total_bytes == 0 {
+                    format!("Upload complete\nEmpty file, time: {:.2?}", self.now.unwrap().elapsed())
+                } else {
+                    format!("Upload complete\nCompressed {} into {} bytes\nRatio: {}, time: {:.2?}",
                         self.total_bytes, self.compressed_bytes, self.compressed_bytes as f32 / self.total_bytes as f32,
-                        self.now.unwrap().elapsed());
+                        self.now.unwrap().elapsed())
+                };
+                if let Some(crc) = self.last_progress.crc {
+                    self.status += &format!("\nCRC: {:#06x}", crc);
+                }
+                if self.last_progress.committed {
+                    self.status += "\nZX Spectrum confirmed the file is written";
+                }
+                if let Some(decision) = self.last_progress.decision {
+                    self.status += &format!("\nSaved as {} ({})",
+                        decision.saved_as(&self.remote_name, self.long_names), decision);
+                }
+                if self.compression == Compression::Auto {
+                    self.status += &format!("\nSaved by quick mode: {}, by optimal mode: {} bytes",
+                        self.last_progress.quick_saved, self.last_progress.optimal_saved);
+                }
+                if self.run {
+                    let saved_as = match self.last_progress.decision {
+                        Some(decision) => decision.saved_as(&self.remote_name, self.long_names),
+                        None => self.remote_name.clone(),
+                    };
+                    if run_command(&saved_as, None).is_ok_and(|command| only_attaches(&command)) {
+                        self.status += "\nAttached on the ZX Spectrum, type LOAD \"\" to start it";
+                    } else {
+                        self.status += "\nStarted on the ZX Spectrum";
+                    }
+                }
                 self.now = None;
-                Task::none()
+                self.resend()
             }
             Message::UploadDone(Err(err)) => {
                 self.sending = false;
                 self.now = None;
                 match err {
-                    UploadError::File => {
-                        self.status = "Failed to read the file!".to_string();
+                    UploadError::File(err) => {
+                        self.status = format!("Failed to read the file!\n{}", err);
                     },
-                    UploadError::Connection => {
-                        self.status = "Connection error, please check the address!".to_string();
+                    UploadError::Connection(err) => {
+                        self.status = format!("Connection error, please check the address!\n{}", err);
+                        self.can_resume = self.last_progress.current_block > 0;
+                    }
+                    UploadError::Receiver(err) => {
+                        self.status = format!("ZX Spectrum failed to receive the file:\n{}", err.error);
+                        if let (Some(block), Some(offset)) = (err.block, err.offset) {
+                            self.status += &format!("\nat block {} (seq {}), offset {}", block, err.seq, offset);
+                        }
+                    }
+                    UploadError::Timeout(err) => {
+                        self.status = format!("ZX Spectrum stopped responding:\n{}", err);
+                        self.can_resume = self.last_progress.current_block > 0;
+                    }
+                    UploadError::Run(err) => {
+                        self.status = format!("File was sent, but can't be run:\n{}", err);
                     }
                 };
-                Task::none()
+                self.resend()
             }
             Message::Uploading(progress) => {
-                self.progress = progress.current_block as f32 / progress.blocks_num as f32;
+                // Empty file is done as soon as it's opened
+                self.progress = if progress.blocks_num == 0 {
+                    1.0
+                } else {
+                    progress.current_block as f32 / progress.blocks_num as f32
+                };
                 self.compressed_bytes = progress.compressed_bytes;
                 self.total_bytes = progress.total_bytes;
+                self.last_progress = progress;
                 Task::none()
             }
             Message::ButtonPressed => {
-                self.sending = true;
-                self.progress = 0f32;
-                self.now = Some(time::Instant::now());
-                let task = Task::sip(FileUploader {
-                        address: if let Some(addr) = self.address.clone() { addr } else { "".to_string() },
-                        filepath: if let Some(path) = self.filepath.clone() { path } else { PathBuf::new() },
-                        use_compression: self.use_compression,
-                        dummy: self.dummy,
-                        }.upload(),
-                    Message::Uploading,
-                    Message::UploadDone);
-                Task::batch(vec![
-                    Task::perform(Config {
-                        address: if let Some(addr) = self.address.clone() { addr } else { "".to_string() },
-                        use_compression: self.use_compression,
-                        dummy: self.dummy,
+                self.last_progress = UploadProgress::default();
+                self.can_resume = false;
+                self.run = false;
+                self.start()
+            }
+            Message::SendAndRunPressed => {
+                self.last_progress = UploadProgress::default();
+                self.can_resume = false;
+                self.run = true;
+                self.start()
+            }
+            Message::ResumePressed => {
+                self.start()
+            }
+            Message::PastePressed => {
+                iced::clipboard::read().map(Message::Pasted)
+            }
+            Message::Pasted(contents) => {
+                match contents.filter(|contents| !contents.is_empty()) {
+                    Some(contents) => {
+                        self.pasted = Some(contents.into_bytes());
+                        self.can_resume = false;
+                        // Clipboard can't be watched
+                        self.watching = None;
+                    }
+                    None => self.status = "Clipboard is empty!".to_string(),
+                }
+                Task::none()
+            }
+            Message::ScanPressed => {
+                if !self.scan_confirming {
+                    self.scan_confirming = true;
+                    self.status = "Scanning makes ZX Spectrums running zxmit older than v0.5 quit,\n\
+                        even in the middle of a transfer. Press \"Scan anyway\" to go on.".to_string();
+                    return Task::none();
+                }
+                self.scan_confirming = false;
+                let port = parse_port(&self.port).unwrap_or(DEFAULT_PORT);
+                self.scanning = true;
+                self.found.clear();
+                self.status = "Looking for ZX Spectrums...".to_string();
+                Task::perform(async move {
+                    discover(port, DEFAULT_PROBE_TIMEOUT).await.map_err(|e| e.to_string())
+                }, Message::ScanDone)
+            }
+            Message::ScanDone(Ok(found)) => {
+                self.scanning = false;
+                let hosts: Vec<String> = found.iter().map(|addr| addr.ip().to_string()).collect();
+                match &hosts[..] {
+                    [] => {
+                        self.status = "No ZX Spectrum found, is zxmit running?".to_string();
+                    }
+                    [host] => {
+                        self.status = format!("Found ZX Spectrum at {}, it's set as the address", host);
+                        self.address = Some(host.clone());
+                    }
+                    hosts => {
+                        self.status = format!("Found {} ZX Spectrums, pick one", hosts.len());
+                        self.found = hosts.to_vec();
                     }
-                    .save_config(),
-                    Message::ConfigSaved),
-                    task,
-                ])
+                }
+                Task::none()
             }
-            Message::UseCompressionChanged(value) => {
-                self.use_compression = value;
+            Message::ScanDone(Err(err)) => {
+                self.scanning = false;
+                self.status = format!("Failed to scan the network!\n{}", err);
+                Task::none()
+            }
+            Message::FoundSelected(host) => {
+                self.address = Some(host);
+                Task::none()
+            }
+            Message::CompressionChanged(value) => {
+                if !self.sending {
+                    self.compression = value;
+                }
                 Task::none()
             }
             Message::DummyChanged(value) => {
                 self.dummy = value;
                 Task::none()
             }
+            Message::LongNamesChanged(value) => {
+                // The interrupted file was sent under the other name
+                self.long_names = value;
+                self.can_resume = false;
+                Task::none()
+            }
+            Message::WatchChanged(value) => {
+                self.watch = value;
+                self.watch()
+            }
+            Message::FileChanged => {
+                self.changed = true;
+                if self.sending {
+                    Task::none()
+                } else {
+                    self.resend()
+                }
+            }
             Message::EventOccurred(event) => {
                 if let Event::Window(WindowEvent::FileDropped(path)) = event {
                     self.filepath = Some(path);
+                    self.pasted = None;
+                    self.save_as.clear();
+                    self.can_resume = false;
+                    return self.watch();
                 }
                 Task::none()
             }
         }
     }
 
+    /// Starts sending the file, continues after `last_progress` if anything
+    /// was acknowledged already
+    fn start(&mut self) -> Task<Message> {
+        let address = if let Some(addr) = self.address.clone() { addr } else { "".to_string() };
+        let parsed = match parse_port(&self.port)
+            .and_then(|port| Address::parse(&address, port)) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.status = format!("Invalid address: {}", err);
+                return Task::none();
+            }
+        };
+        let pacing = match (parse_or_zero(&self.rate), parse_or_zero(&self.delay)) {
+            (Some(rate), Some(delay)) => Pacing::from_rate_and_millis(rate, delay),
+            _ => {
+                self.status = "Rate and delay must be whole numbers".to_string();
+                return Task::none();
+            }
+        };
+        // Checked before connecting, the Spectrum would only report a failure
+        let destination = match Destination::parse(&self.destination, self.long_names) {
+            Ok(destination) => destination,
+            Err(err) => {
+                self.status = format!("Invalid destination: {}", err);
+                return Task::none();
+            }
+        };
+        let source = match &self.pasted {
+            Some(data) => Source::Clipboard(data.clone()),
+            None => Source::File(self.filepath.clone().unwrap_or_default()),
+        };
+        let name = match (self.save_as.trim(), &source) {
+            ("", Source::Clipboard(_)) => {
+                self.status = "Pasted data needs a name to save it under".to_string();
+                return Task::none();
+            }
+            ("", Source::File(filepath)) => {
+                let basename = filepath.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                if self.long_names { filename_to_long(basename) } else { filename_to_short(basename) }
+            }
+            (name, _) => match remote_name(name, self.long_names) {
+                Ok(name) => name,
+                Err(err) => {
+                    self.status = format!("Invalid name: {}", err);
+                    return Task::none();
+                }
+            },
+        };
+        // Checked before sending, there's no point in it if the file can't be run
+        if self.run && let Err(err) = run_command(&name, None) {
+            self.status = format!("Can't run the file: {}", err);
+            return Task::none();
+        }
+        self.remote_name = name.clone();
+        self.pacing = pacing;
+
+        let resume = if self.can_resume { Some(self.last_progress.clone()) } else { None };
+        self.can_resume = false;
+        self.sending = true;
+        self.progress = 0f32;
+        self.now = Some(time::Instant::now());
+        let task = Task::sip(FileUploader {
+                address: parsed,
+                source,
+                compression: self.compression,
+                use_crc: self.use_crc,
+                dummy: self.dummy,
+                timeouts: self.timeouts,
+                window: self.window,
+                pacing,
+                long_names: self.long_names,
+                name,
+                destination,
+                collision: self.collision,
+                resume,
+                run: self.run,
+                }.upload(),
+            Message::Uploading,
+            Message::UploadDone);
+        let watch = self.watch();
+        Task::batch(vec![
+            Task::perform(Config {
+                address,
+                port: parse_port(&self.port).unwrap_or(DEFAULT_PORT),
+                use_compression: self.compression != Compression::None,
+                compression: Some(self.compression.to_string()),
+                dummy: self.dummy,
+                use_crc: self.use_crc,
+                connect_timeout: self.timeouts.connect.map_or(0, |t| t.as_secs()),
+                write_timeout: self.timeouts.write.map_or(0, |t| t.as_secs()),
+                ack_timeout: self.timeouts.ack.map_or(0, |t| t.as_secs()),
+                window: self.window,
+                long_names: self.long_names,
+                destination: self.destination.clone(),
+                collision: self.collision.map(|c| c.to_string()),
+                rate: pacing.rate.unwrap_or(0),
+                delay: pacing.delay.as_millis() as u64,
+            }
+            .save_config(),
+            Message::ConfigSaved),
+            task,
+            watch,
+        ])
+    }
+
+    /// Waits for the dropped file to change if it's watched, stops waiting
+    /// for the previous one
+    fn watch(&mut self) -> Task<Message> {
+        self.watching = None;
+        let Some(filepath) = self.filepath.clone().filter(|_| self.watch && self.pasted.is_none()) else {
+            return Task::none();
+        };
+        let mut watcher = Watcher::new([filepath], DEFAULT_DEBOUNCE);
+        let (task, handle) = Task::perform(async move { watcher.changed().await }, |_| Message::FileChanged)
+            .abortable();
+        self.watching = Some(handle.abort_on_drop());
+        task
+    }
+
+    /// Sends the file again if it changed while it was being sent
+    fn resend(&mut self) -> Task<Message> {
+        if !std::mem::take(&mut self.changed) {
+            return Task::none();
+        }
+        self.last_progress = UploadProgress::default();
+        self.can_resume = false;
+        self.start()
+    }
+
     fn subscription(&self) -> Subscription<Message> {
         Subscription::batch(vec![ 
             iced::event::listen().map(Message::EventOccurred),
@@ -260,26 +698,94 @@ impl App {
             }
         };
 
-        let text_input = text_input("Enter ZX Spectrum IP Address here", address)
+        let address_input = text_input("Enter ZX Spectrum IP Address or host name here", address)
             .on_input(Message::AddressChanged)
             .padding(10)
             .size(20);
 
-        let filename = match &self.filepath {
-            Some(filepath) => text(filepath.clone().into_os_string().into_string().unwrap()),
-            None => {
+        let port_input = text_input("Port", &self.port)
+            .on_input(Message::PortChanged)
+            .padding(10)
+            .size(20)
+            .width(Length::Fixed(90.0));
+
+        let scan_button = button(if self.scan_confirming { "Scan anyway" } else { "Scan" })
+            .padding(10)
+            .on_press_maybe(if !self.sending && !self.scanning {
+                    Some(Message::ScanPressed)
+                } else {
+                    None
+                });
+
+        let address_row = row![
+            address_input,
+            port_input,
+            scan_button,
+        ]
+        .spacing(10);
+
+        let found = (self.found.len() > 1).then(|| {
+            let selected = self.address.clone().filter(|address| self.found.contains(address));
+            pick_list(&self.found[..], selected, Message::FoundSelected)
+                .placeholder("Pick ZX Spectrum")
+                .padding(5)
+        });
+
+        let filename = match (&self.pasted, &self.filepath) {
+            (Some(data), _) => text(format!("Pasted from the clipboard, {} bytes", data.len())),
+            (None, Some(filepath)) => text(filepath.clone().into_os_string().into_string().unwrap()),
+            (None, None) => {
                 button_enabled = false;
-                text("Drop the file here!")
+                text("Drop the file here or paste it!")
             },
         };
 
 
+        let save_as_input = text_input("Save as", &self.save_as)
+            .on_input(Message::SaveAsChanged)
+            .padding(10);
+
+        let destination_input = text_input("Destination, e.g. C:/GAMES", &self.destination)
+            .on_input(Message::DestinationChanged)
+            .padding(10);
+
+        let remote_row = row![
+            save_as_input,
+            destination_input,
+        ]
+        .spacing(10);
+
+        let collision = pick_list(&Collision::ALL[..], self.collision, Message::CollisionChanged)
+            .placeholder("overwrite silently")
+            .padding(5);
+
+        let collision_row = row![
+            text("If the file exists"),
+            collision,
+        ]
+        .spacing(10)
+        .align_y(Center);
+
+        let rate_input = text_input("Rate, bytes/s", &self.rate)
+            .on_input(Message::RateChanged)
+            .padding(10);
+
+        let delay_input = text_input("Delay after block, ms", &self.delay)
+            .on_input(Message::DelayChanged)
+            .padding(10);
+
+        let pacing_row = row![
+            rate_input,
+            delay_input,
+        ]
+        .spacing(10);
+
         let button_text = if self.sending {
             "Working..."
         } else {
             "Send!"
         };
-        let button = button(button_text)
+        let send_button = button(button_text)
             .padding(10)
             .on_press_maybe(if button_enabled {
                     Some(Message::ButtonPressed)
@@ -287,44 +793,116 @@ impl App {
                     None
                 });
 
-        let use_compression = checkbox("Use compression", self.use_compression)
+        let send_and_run_button = button("Send & run")
+            .padding(10)
+            .on_press_maybe(if button_enabled {
+                    Some(Message::SendAndRunPressed)
+                } else {
+                    None
+                });
+
+        let resume_button = button("Resume")
+            .padding(10)
+            .on_press_maybe(if button_enabled && self.can_resume {
+                    Some(Message::ResumePressed)
+                } else {
+                    None
+                });
+
+        let paste_button = button("Paste")
+            .padding(10)
+            .on_press_maybe(if !self.sending {
+                    Some(Message::PastePressed)
+                } else {
+                    None
+                });
+
+        let watch = checkbox("Watch", self.watch)
+            .on_toggle(Message::WatchChanged);
+
+        let source_row = row![
+            paste_button,
+            watch,
+        ]
+        .spacing(20)
+        .align_y(Center);
+
+        let buttons = row![
+            send_button,
+            send_and_run_button,
+            resume_button,
+        ]
+        .spacing(10)
+        .align_y(Center);
+
+        let compression = pick_list(&Compression::ALL[..], Some(self.compression), Message::CompressionChanged)
+            .padding(5);
+
+        let dummy = checkbox("Dummy run", self.dummy)
             .on_toggle_maybe(if !self.sending {
-                Some (Message::UseCompressionChanged)
+                Some (Message::DummyChanged)
             } else {
             None
             });
 
-        let dummy = checkbox("Dummy run", self.dummy)
+        let long_names = checkbox("Long names", self.long_names)
             .on_toggle_maybe(if !self.sending {
-                Some (Message::DummyChanged)
+                Some (Message::LongNamesChanged)
             } else {
             None
             });
 
+        let compression_row = row![
+            text("Compression"),
+            compression,
+        ]
+        .spacing(10)
+        .align_y(Center);
+
         let checkboxes = row![
-            use_compression,
             dummy,
+            long_names,
         ]
         .spacing(20)
-        .padding(20);
+        .align_y(Center);
 
         let status: Element<Message> = if self.sending {
-            progress_bar(0.0..=1.0, self.progress).into()
+            let eta = self.now.filter(|_| self.last_progress.total_bytes > 0)
+                .and_then(|now| self.last_progress.eta(now.elapsed(), &self.pacing))
+                .map_or(String::new(), |eta| format!("{:.0?} left", eta));
+            column![
+                progress_bar(0.0..=1.0, self.progress),
+                text(eta),
+            ]
+            .spacing(5)
+            .align_x(Center)
+            .into()
+        } else if self.watching.is_some() {
+            text(format!("{}\nWatching for changes...", self.status).trim_start().to_string()).align_x(Center).into()
         } else {
             text(&self.status).align_x(Center).into()
         };
 
-        let content = column![
-            text_input,
-            filename,
-            button,
-            checkboxes,
-            status,
-        ]
-        .align_x(Center)
-        .spacing(20)
-        .padding(20)
-        .max_width(400);
+        let mut content = column![
+            address_row,
+        ];
+        if let Some(found) = found {
+            content = content.push(found);
+        }
+        let content = content
+            .push(filename)
+            .push(source_row)
+            .push(remote_row)
+            .push(collision_row)
+            .push(compression_row)
+            .push(pacing_row)
+            .push(checkboxes)
+            .push(buttons)
+            .push(status)
+            .align_x(Center)
+            .spacing(20)
+            .padding(20)
+            .max_width(480);
 
         center(content)
             .into()